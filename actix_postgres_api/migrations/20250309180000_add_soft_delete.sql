@@ -0,0 +1,11 @@
+-- Soft delete: usunięte konta zostają w tabeli z wypełnionym deleted_at
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- Unikalność egzekwujemy tylko dla aktywnych kont, żeby polityka ponownego
+-- użycia nazw po usuniętych kontach mogła być konfigurowana w aplikacji
+ALTER TABLE users DROP CONSTRAINT users_username_key;
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+CREATE UNIQUE INDEX users_username_active_key ON users (username) WHERE deleted_at IS NULL;
+CREATE UNIQUE INDEX users_email_active_key ON users (email) WHERE deleted_at IS NULL;
+
+COMMENT ON COLUMN users.deleted_at IS 'Moment usunięcia konta (soft delete); NULL dla aktywnych rekordów';
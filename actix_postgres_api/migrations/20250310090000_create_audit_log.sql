@@ -0,0 +1,12 @@
+-- Dziennik zdarzeń administracyjnych i bezpieczeństwa
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    action VARCHAR(100) NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    details JSONB NOT NULL DEFAULT '{}'::jsonb,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_log_action_created_at_idx ON audit_log (action, created_at);
+
+COMMENT ON COLUMN audit_log.actor IS 'Kto wykonał akcję, np. "admin" dla żądań z kluczem administratora';
@@ -0,0 +1,6 @@
+-- Indeksy trigramowe dla wyszukiwania ILIKE '%fraza%' po nazwie użytkownika i imieniu
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Indeksy częściowe - pasują do warunku deleted_at IS NULL z zapytania wyszukiwania
+CREATE INDEX users_username_trgm_idx ON users USING gin (username gin_trgm_ops) WHERE deleted_at IS NULL;
+CREATE INDEX users_full_name_trgm_idx ON users USING gin (full_name gin_trgm_ops) WHERE deleted_at IS NULL;
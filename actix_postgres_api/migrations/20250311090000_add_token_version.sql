@@ -0,0 +1,2 @@
+-- Wersja tokenów użytkownika; podbicie wartości unieważnia wszystkie wydane tokeny
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,17 @@
+-- Treści nieudanych żądań modyfikujących, zapisywane tylko w trybie przechwytywania
+CREATE TABLE failed_requests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    request_id UUID NOT NULL UNIQUE,
+    method VARCHAR(10) NOT NULL,
+    route VARCHAR(255) NOT NULL,
+    status SMALLINT NOT NULL,
+    error_code VARCHAR(50),
+    body JSONB,
+    body_size INTEGER NOT NULL,
+    body_truncated BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX failed_requests_created_at_idx ON failed_requests (created_at);
+
+COMMENT ON COLUMN failed_requests.body IS 'Treść żądania z zamaskowanymi polami haseł i tokenów; NULL, gdy przekracza limit rozmiaru lub nie jest JSON-em';
@@ -0,0 +1,7 @@
+-- Czasowe przypisanie roli; po upływie role_expires_at rola wraca do domyślnej
+ALTER TABLE users ADD COLUMN role_expires_at TIMESTAMPTZ;
+
+-- Zadanie wygaszające role przegląda tylko konta z ustawionym terminem
+CREATE INDEX users_role_expires_at_idx ON users (role_expires_at) WHERE role_expires_at IS NOT NULL AND deleted_at IS NULL;
+
+COMMENT ON COLUMN users.role_expires_at IS 'Moment wygaśnięcia roli; NULL dla ról bezterminowych';
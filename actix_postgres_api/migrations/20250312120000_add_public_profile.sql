@@ -0,0 +1,5 @@
+-- Zgoda trenera na publikację profilu na stronie internetowej
+ALTER TABLE users ADD COLUMN public_profile BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX users_public_trainers_idx ON users (username)
+    WHERE public_profile AND role = 'trainer' AND active AND deleted_at IS NULL;
@@ -0,0 +1,12 @@
+-- Potwierdzenie numeru telefonu kodem SMS
+ALTER TABLE users ADD COLUMN phone_verified_at TIMESTAMPTZ;
+
+-- Co najwyżej jeden oczekujący kod na użytkownika; nowy kod zastępuje poprzedni
+CREATE TABLE phone_verifications (
+    user_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    phone_number VARCHAR(20) NOT NULL,
+    code_hash TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
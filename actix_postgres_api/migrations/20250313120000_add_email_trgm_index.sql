@@ -0,0 +1,2 @@
+-- Wyszukiwanie łączone dla administratorów dopasowuje także adresy email
+CREATE INDEX users_email_trgm_idx ON users USING gin (email gin_trgm_ops) WHERE deleted_at IS NULL;
@@ -0,0 +1,4 @@
+-- Pola profilu przypisane do jednej roli: opis i stawka trenera, cele klienta
+ALTER TABLE users ADD COLUMN bio TEXT;
+ALTER TABLE users ADD COLUMN hourly_rate INTEGER CHECK (hourly_rate >= 0);
+ALTER TABLE users ADD COLUMN fitness_goals TEXT;
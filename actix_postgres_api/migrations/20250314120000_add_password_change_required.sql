@@ -0,0 +1,2 @@
+-- Konto z hasłem tymczasowym (reset przez administratora) musi zmienić hasło przy następnym logowaniu
+ALTER TABLE users ADD COLUMN password_change_required BOOLEAN NOT NULL DEFAULT false;
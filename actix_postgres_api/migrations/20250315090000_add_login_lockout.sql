@@ -0,0 +1,6 @@
+-- Blokada konta po serii nieudanych logowań; czas blokady rośnie z każdą kolejną
+-- blokadą w oknie obserwacji (lockout_count, liczony od last_lockout_at)
+ALTER TABLE users ADD COLUMN failed_login_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN lockout_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN last_lockout_at TIMESTAMPTZ;
@@ -0,0 +1,26 @@
+-- Słownik specjalizacji trenerów; slug jest wartością przyjmowaną przez API
+CREATE TABLE specialties (
+    slug VARCHAR(50) PRIMARY KEY,
+    name VARCHAR(100) NOT NULL
+);
+
+INSERT INTO specialties (slug, name) VALUES
+    ('yoga', 'Yoga'),
+    ('pilates', 'Pilates'),
+    ('strength', 'Strength training'),
+    ('crossfit', 'CrossFit'),
+    ('cardio', 'Cardio'),
+    ('mobility', 'Mobility and stretching'),
+    ('nutrition', 'Nutrition'),
+    ('rehabilitation', 'Rehabilitation'),
+    ('boxing', 'Boxing'),
+    ('running', 'Running');
+
+-- Specjalizacje przypisane trenerom
+CREATE TABLE trainer_specialties (
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    specialty VARCHAR(50) NOT NULL REFERENCES specialties (slug),
+    PRIMARY KEY (user_id, specialty)
+);
+
+CREATE INDEX idx_trainer_specialties_specialty ON trainer_specialties (specialty);
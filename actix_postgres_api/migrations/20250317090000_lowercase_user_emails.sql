@@ -0,0 +1,28 @@
+-- Adresy email porównujemy bez względu na wielkość liter; API zapisuje je już małymi literami
+
+-- Aktywne konta różniące się tylko wielkością liter: najstarsze zachowuje adres,
+-- pozostałe dostają adres z dopiskiem "+conflict-<id>", a oryginał trafia do audit_log
+WITH ranked AS (
+    SELECT id, email,
+           ROW_NUMBER() OVER (PARTITION BY lower(email) ORDER BY created_at, id) AS position
+    FROM users
+    WHERE deleted_at IS NULL
+),
+conflicts AS (
+    SELECT id, email FROM ranked WHERE position > 1
+),
+audited AS (
+    INSERT INTO audit_log (action, actor, details)
+    SELECT 'email_case_conflict', 'migration', jsonb_build_object('user_id', id, 'original_email', email)
+    FROM conflicts
+)
+UPDATE users u
+SET email = split_part(lower(c.email), '@', 1) || '+conflict-' || left(c.id::text, 8) || '@' || split_part(lower(c.email), '@', 2)
+FROM conflicts c
+WHERE u.id = c.id;
+
+UPDATE users SET email = lower(email) WHERE email <> lower(email);
+
+-- Ta sama nazwa indeksu, żeby naruszenie unikalności nadal wskazywało pole email
+DROP INDEX users_email_active_key;
+CREATE UNIQUE INDEX users_email_active_key ON users (lower(email)) WHERE deleted_at IS NULL;
@@ -0,0 +1,8 @@
+-- Indeksy pod raport duplikatów: kandydaci wyszukiwani są przez indeks (operator % i
+-- równość cyfr telefonu) zamiast porównywania każdej pary kont
+CREATE INDEX users_email_local_trgm_idx ON users
+    USING gin ((split_part(split_part(email, '@', 1), '+', 1)) gin_trgm_ops) WHERE deleted_at IS NULL;
+CREATE INDEX users_full_name_lower_trgm_idx ON users
+    USING gin (lower(full_name) gin_trgm_ops) WHERE deleted_at IS NULL;
+CREATE INDEX users_phone_digits_idx ON users (regexp_replace(phone_number, '\D', '', 'g'))
+    WHERE deleted_at IS NULL AND phone_number IS NOT NULL;
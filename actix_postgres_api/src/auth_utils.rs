@@ -1,60 +1,343 @@
-use bcrypt::{hash, verify, DEFAULT_COST};
+use bcrypt::{non_truncating_hash, verify, BcryptError, DEFAULT_COST};
+use rand::seq::{IndexedRandom, SliceRandom};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use regex::Regex;
-use crate::error::AppError;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use tokio::sync::oneshot;
+use crate::config::{CharacterClass, PasswordPolicy, RoleFieldPolicy};
+use crate::error::{AppError, FieldError};
+use crate::monitoring::{RequestTimings, Timer};
+use crate::models::{CreateUserRequest, PasswordRequirementResult, RoleSpecificFields, UserRole};
+use crate::types::{Email, ParseEnum, PhoneNumber, Username};
 
-/// Generuje hash hasła z użyciem bcrypt
-pub fn hash_password(password: &str) -> Result<String, AppError> {
-    hash(password, DEFAULT_COST)
-        .map_err(|e| AppError::InternalServerError(format!("Hashing error: {}", e)))
+// bcrypt obciąża CPU przez setki milisekund, więc hashowanie odbywa się na
+// osobnej puli wątków zamiast blokować wątki runtime'u obsługujące żądania
+static HASHING_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+fn build_hashing_pool(threads: usize) -> ThreadPool {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("password-hash-{}", index))
+        .build()
+        .expect("Failed to build password hashing thread pool")
+}
+
+/// Sets the size of the password hashing pool; must be called before the first hash.
+///
+/// Without it the pool is created on first use with one thread per CPU.
+pub fn init_hashing_pool(threads: usize) {
+    if HASHING_POOL.set(build_hashing_pool(threads)).is_err() {
+        tracing::warn!("Password hashing pool already initialized, ignoring size {}", threads);
+    }
+}
+
+// Uruchamia zadanie na puli hashowania i czeka na wynik bez blokowania runtime'u
+async fn run_on_hashing_pool<T, F>(job: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, AppError> + Send + 'static,
+{
+    let pool = HASHING_POOL.get_or_init(|| build_hashing_pool(0));
+    let (sender, receiver) = oneshot::channel();
+    // Czas hashowania raportujemy osobno (Server-Timing `hash`), a nie jako czas bazy
+    let timer = Timer::new();
+    pool.spawn(move || {
+        let _ = sender.send(job());
+    });
+
+    let result = receiver.await;
+    RequestTimings::record_hash(timer.elapsed_seconds());
+    result.map_err(|_| AppError::InternalServerError("Password hashing task was dropped".to_string()))?
+}
+
+/// bcrypt only uses the first 72 bytes of a password
+pub const BCRYPT_MAX_PASSWORD_BYTES: usize = 72;
+
+fn password_too_long() -> AppError {
+    AppError::ValidationError(format!(
+        "Password must be at most {} bytes long",
+        BCRYPT_MAX_PASSWORD_BYTES
+    ))
 }
 
-/// Weryfikuje, czy podane hasło odpowiada hashowi
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
-    verify(password, hash)
-        .map_err(|e| AppError::InternalServerError(format!("Verification error: {}", e)))
+/// Generuje hash hasła z użyciem bcrypt; dłuższe hasła są odrzucane zamiast obcinane
+pub async fn hash_password(password: &str) -> Result<String, AppError> {
+    let password = password.to_string();
+    run_on_hashing_pool(move || {
+        non_truncating_hash(password, DEFAULT_COST).map_err(|e| match e {
+            BcryptError::Truncation(_) => password_too_long(),
+            e => AppError::InternalServerError(format!("Hashing error: {}", e)),
+        })
+    })
+    .await
 }
 
-// Funkcja pomocnicza do walidacji siły hasła
-pub fn validate_password(password: &str) -> Result<(), AppError> {
-    if password.len() < 8 {
-        return Err(AppError::ValidationError("Password must be at least 8 characters long".to_string()));
+/// Weryfikuje, czy podane hasło odpowiada hashowi.
+///
+/// Konta sprzed limitu mogą mieć hasła dłuższe niż 72 bajty, z których bcrypt
+/// uwzględnił tylko początek. Obcinamy je jawnie i wyłącznie dla hashy bcrypt,
+/// więc każde hasło o tym samym 72-bajtowym prefiksie pasuje do takiego hasha,
+/// ale inny algorytm (bez obcinania) nie odziedziczy tej właściwości.
+pub async fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
+    if !is_bcrypt_hash(hash) {
+        return Err(AppError::InternalServerError("Unsupported password hash format".to_string()));
     }
     
-    // Sprawdź, czy hasło zawiera cyfrę
-    if !password.chars().any(|c| c.is_digit(10)) {
-        return Err(AppError::ValidationError("Password must contain at least one digit".to_string()));
+    let mut password = password.as_bytes().to_vec();
+    password.truncate(BCRYPT_MAX_PASSWORD_BYTES);
+    let hash = hash.to_string();
+    run_on_hashing_pool(move || {
+        verify(password, &hash)
+            .map_err(|e| AppError::InternalServerError(format!("Verification error: {}", e)))
+    })
+    .await
+}
+
+/// Checks `password` against a throwaway hash, so a login for an unknown account
+/// takes as long as one with a wrong password
+pub async fn verify_dummy_password(password: &str) -> Result<(), AppError> {
+    static DUMMY_HASH: tokio::sync::OnceCell<String> = tokio::sync::OnceCell::const_new();
+    let hash = DUMMY_HASH.get_or_try_init(|| hash_password("dummy-password-for-timing")).await?;
+    verify_password(password, hash).await.map(|_| ())
+}
+
+/// Compares two secrets in time that depends only on their lengths
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+// Funkcja pomocnicza do walidacji siły hasła według obowiązującej polityki
+pub fn validate_password(password: &str, policy: &PasswordPolicy) -> Result<(), AppError> {
+    if password.chars().count() < policy.min_length {
+        return Err(AppError::ValidationError(format!(
+            "Password must be at least {} characters long",
+            policy.min_length
+        )));
     }
     
-    // Sprawdź, czy hasło zawiera dużą literę
-    if !password.chars().any(|c| c.is_uppercase()) {
-        return Err(AppError::ValidationError("Password must contain at least one uppercase letter".to_string()));
+    if password.len() > BCRYPT_MAX_PASSWORD_BYTES {
+        return Err(password_too_long());
     }
     
-    // Sprawdź, czy hasło zawiera małą literę
-    if !password.chars().any(|c| c.is_lowercase()) {
-        return Err(AppError::ValidationError("Password must contain at least one lowercase letter".to_string()));
+    for class in &policy.required_classes {
+        if !password.chars().any(|c| character_class_matches(*class, c, policy)) {
+            let message = match class {
+                CharacterClass::Digit => "Password must contain at least one digit",
+                CharacterClass::Uppercase => "Password must contain at least one uppercase letter",
+                CharacterClass::Lowercase => "Password must contain at least one lowercase letter",
+                CharacterClass::Special => "Password must contain at least one special character",
+            };
+            return Err(AppError::ValidationError(message.to_string()));
+        }
+    }
+    
+    if password_entropy_bits(password, policy) < policy.min_entropy_bits {
+        return Err(AppError::ValidationError(format!(
+            "Password is too predictable (needs at least {} bits of entropy)",
+            policy.min_entropy_bits
+        )));
     }
     
     Ok(())
 }
 
-// Nowa funkcja walidacji adresu email
+fn character_class_matches(class: CharacterClass, c: char, policy: &PasswordPolicy) -> bool {
+    match class {
+        CharacterClass::Lowercase => c.is_lowercase(),
+        CharacterClass::Uppercase => c.is_uppercase(),
+        CharacterClass::Digit => c.is_ascii_digit(),
+        CharacterClass::Special => policy.special_characters.contains(c),
+    }
+}
+
+/// Szacuje entropię hasła jako długość * log2(liczba znaków w użytych klasach)
+pub fn password_entropy_bits(password: &str, policy: &PasswordPolicy) -> f64 {
+    let pool: usize = [
+        (CharacterClass::Lowercase, 26),
+        (CharacterClass::Uppercase, 26),
+        (CharacterClass::Digit, 10),
+        (CharacterClass::Special, policy.special_characters.chars().count()),
+    ]
+    .into_iter()
+    .filter(|(class, _)| password.chars().any(|c| character_class_matches(*class, c, policy)))
+    .map(|(_, size)| size)
+    .sum();
+    
+    if pool == 0 {
+        return 0.0;
+    }
+    password.chars().count() as f64 * (pool as f64).log2()
+}
+
+// Lista popularnych haseł wkompilowana w binarkę; wiersze z # to komentarze
+static COMMON_PASSWORDS: OnceLock<HashSet<String>> = OnceLock::new();
+
+/// Whether the password is on the built-in list of common passwords (case-insensitive)
+pub fn is_common_password(password: &str) -> bool {
+    COMMON_PASSWORDS
+        .get_or_init(|| {
+            include_str!("common_passwords.txt")
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect()
+        })
+        .contains(&password.to_lowercase())
+}
+
+/// Checks a candidate password against each policy requirement separately, plus
+/// the advisory common-password check; nothing is enforced or stored
+pub fn password_checklist(password: &str, policy: &PasswordPolicy) -> Vec<PasswordRequirementResult> {
+    let requirement = |id: &'static str, passed: bool, enforced: bool, message: String| PasswordRequirementResult {
+        requirement: id,
+        passed,
+        enforced,
+        message,
+    };
+    
+    let mut checklist = vec![
+        requirement(
+            "min_length",
+            password.chars().count() >= policy.min_length,
+            true,
+            format!("At least {} characters", policy.min_length),
+        ),
+        requirement(
+            "max_length",
+            password.len() <= BCRYPT_MAX_PASSWORD_BYTES,
+            true,
+            format!("At most {} bytes", BCRYPT_MAX_PASSWORD_BYTES),
+        ),
+    ];
+    for class in &policy.required_classes {
+        let (id, message) = match class {
+            CharacterClass::Lowercase => ("lowercase", "A lowercase letter"),
+            CharacterClass::Uppercase => ("uppercase", "An uppercase letter"),
+            CharacterClass::Digit => ("digit", "A digit"),
+            CharacterClass::Special => ("special", "A special character"),
+        };
+        let passed = password.chars().any(|c| character_class_matches(*class, c, policy));
+        checklist.push(requirement(id, passed, true, message.to_string()));
+    }
+    if policy.min_entropy_bits > 0.0 {
+        checklist.push(requirement(
+            "min_entropy",
+            password_entropy_bits(password, policy) >= policy.min_entropy_bits,
+            true,
+            format!("At least {} bits of estimated entropy", policy.min_entropy_bits),
+        ));
+    }
+    checklist.push(requirement(
+        "not_common",
+        !is_common_password(password),
+        false,
+        "Not a commonly used password".to_string(),
+    ));
+    checklist
+}
+
+// Tymczasowe hasła mają co najmniej tyle znaków, niezależnie od polityki
+const GENERATED_PASSWORD_MIN_LENGTH: usize = 20;
+
+/// Generates a random password that satisfies `policy`, e.g. for admin resets
+pub fn generate_password(policy: &PasswordPolicy) -> Result<String, AppError> {
+    // Tylko znaki ASCII, żeby długość w bajtach odpowiadała liczbie znaków
+    let special: Vec<char> = policy.special_characters.chars().filter(char::is_ascii).collect();
+    let classes: Vec<(CharacterClass, Vec<char>)> = vec![
+        (CharacterClass::Lowercase, ('a'..='z').collect()),
+        (CharacterClass::Uppercase, ('A'..='Z').collect()),
+        (CharacterClass::Digit, ('0'..='9').collect()),
+        (CharacterClass::Special, special),
+    ];
+    let alphabet: Vec<char> = classes.iter().flat_map(|(_, chars)| chars.iter().copied()).collect();
+    
+    let entropy_length = (policy.min_entropy_bits / (alphabet.len() as f64).log2()).ceil() as usize;
+    let length = GENERATED_PASSWORD_MIN_LENGTH
+        .max(policy.min_length)
+        .max(entropy_length)
+        .min(BCRYPT_MAX_PASSWORD_BYTES);
+    
+    let mut rng = rand::rng();
+    // Po jednym znaku z każdej wymaganej klasy, reszta z całego alfabetu
+    let mut password: Vec<char> = classes
+        .iter()
+        .filter(|(class, _)| policy.required_classes.contains(class))
+        .filter_map(|(_, chars)| chars.choose(&mut rng).copied())
+        .collect();
+    while password.len() < length {
+        password.push(*alphabet.choose(&mut rng).expect("alphabet is never empty"));
+    }
+    password.shuffle(&mut rng);
+    
+    let password: String = password.into_iter().collect();
+    validate_password(&password, policy).map_err(|e| {
+        AppError::InternalServerError(format!("Password policy cannot be satisfied by generated passwords: {}", e))
+    })?;
+    Ok(password)
+}
+
+/// Maximum length of the part before `@` (RFC 5321)
+pub const EMAIL_MAX_LOCAL_PART_LENGTH: usize = 64;
+/// Maximum length of a whole address, as it must fit in an SMTP path (RFC 5321)
+pub const EMAIL_MAX_LENGTH: usize = 254;
+const EMAIL_MAX_LABEL_LENGTH: usize = 63;
+
+// Walidacja adresu email bez wyrażeń regularnych: długości sprawdzane są przed
+// jakimkolwiek skanowaniem, więc kilka liniowych przejść ma ograniczony koszt
 pub fn validate_email(email: &str) -> Result<(), AppError> {
-    // Wyrażenie regularne dla walidacji podstawowego formatu email
-    let email_regex = Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$")
-        .map_err(|_| AppError::InternalServerError("Failed to compile regex".to_string()))?;
+    let invalid = || AppError::ValidationError("Invalid email format".to_string());
 
-    if !email_regex.is_match(email) {
-        return Err(AppError::ValidationError("Invalid email format".to_string()));
+    if email.len() > EMAIL_MAX_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Email is too long (max {} characters)",
+            EMAIL_MAX_LENGTH
+        )));
     }
 
-    // Dodatkowe sprawdzenia
-    if email.len() > 100 {
-        return Err(AppError::ValidationError("Email is too long (max 100 characters)".to_string()));
+    let (local, domain) = email
+        .split_once('@')
+        .ok_or_else(|| AppError::ValidationError("Email must contain @ character".to_string()))?;
+
+    if local.len() > EMAIL_MAX_LOCAL_PART_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Email local part is too long (max {} characters)",
+            EMAIL_MAX_LOCAL_PART_LENGTH
+        )));
     }
 
-    if !email.contains('@') {
-        return Err(AppError::ValidationError("Email must contain @ character".to_string()));
+    // Część lokalna: bez cudzysłowów, kropka nie na brzegu i nie podwójna
+    let local_ok = !local.is_empty()
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local.bytes().all(|b| b.is_ascii_alphanumeric() || b"._%+-".contains(&b));
+    if !local_ok {
+        return Err(invalid());
+    }
+
+    // Domena: co najmniej dwie etykiety, domena najwyższego poziomu złożona z liter
+    let mut labels = domain.split('.').peekable();
+    let mut label_count = 0;
+    while let Some(label) = labels.next() {
+        label_count += 1;
+        let label_ok = !label.is_empty()
+            && label.len() <= EMAIL_MAX_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        let is_tld = labels.peek().is_none();
+        if !label_ok || (is_tld && (label.len() < 2 || !label.bytes().all(|b| b.is_ascii_alphabetic()))) {
+            return Err(invalid());
+        }
+    }
+    if label_count < 2 {
+        return Err(invalid());
     }
 
     Ok(())
@@ -73,7 +356,7 @@ pub fn validate_phone_number(phone: &str) -> Result<(), AppError> {
     }
 
     // Sprawdź, czy numer zawiera wystarczającą liczbę cyfr
-    let digit_count = phone.chars().filter(|c| c.is_digit(10)).count();
+    let digit_count = phone.chars().filter(|c| c.is_ascii_digit()).count();
     if digit_count < 6 {
         return Err(AppError::ValidationError(
             "Phone number must contain at least 6 digits".to_string()
@@ -137,8 +420,164 @@ pub fn validate_full_name(full_name: &str) -> Result<(), AppError> {
 
 // Funkcja walidująca rolę użytkownika
 pub fn validate_role(role: &str) -> Result<String, AppError> {
-    match role.to_lowercase().as_str() {
-        "client" | "trainer" => Ok(role.to_lowercase()),
-        _ => Err(AppError::ValidationError("Invalid role. Must be 'client' or 'trainer'".to_string()))
+    UserRole::parse(role).map(|role| role.as_str().to_string())
+}
+
+/// Profile fields only one role may set, keyed by the role `validate_role` returns
+pub const ROLE_SPECIFIC_FIELDS: &[(&str, &str)] = &[
+    ("bio", "trainer"),
+    ("hourly_rate", "trainer"),
+    ("fitness_goals", "client"),
+    ("specialties", "trainer"),
+];
+
+/// Normalizes submitted specialty slugs: lowercase, sorted, without duplicates
+pub fn normalize_specialties(specialties: Vec<String>) -> Vec<String> {
+    let mut specialties: Vec<String> = specialties.into_iter().map(|slug| slug.to_lowercase()).collect();
+    specialties.sort();
+    specialties.dedup();
+    specialties
+}
+
+/// Whether an account with `role` may set `field`; fields without a rule are open to every role
+pub fn field_allowed_for_role(field: &str, role: &str) -> bool {
+    ROLE_SPECIFIC_FIELDS
+        .iter()
+        .find(|(restricted, _)| *restricted == field)
+        .is_none_or(|(_, owner)| *owner == role)
+}
+
+/// Checks role-specific fields against an already validated role; depending on
+/// `policy` the misplaced ones are rejected with `Forbidden` or dropped.
+/// On rejection the first misplaced field is returned along with the error
+pub fn enforce_role_fields(
+    role: &str,
+    fields: &mut RoleSpecificFields,
+    policy: RoleFieldPolicy,
+) -> Result<(), (&'static str, AppError)> {
+    let misplaced: Vec<&'static str> = fields
+        .supplied()
+        .into_iter()
+        .filter(|field| !field_allowed_for_role(field, role))
+        .collect();
+    let Some(&first) = misplaced.first() else {
+        return Ok(());
+    };
+
+    match policy {
+        RoleFieldPolicy::Reject => Err((
+            first,
+            AppError::Forbidden(format!("{} cannot be set on a {} account", misplaced.join(", "), role)),
+        )),
+        RoleFieldPolicy::Strip => {
+            for field in misplaced {
+                fields.clear(field);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Fields a registration must include for a given role, on top of the ones
+/// every account needs; pairs of (role, field)
+pub const ROLE_REQUIRED_FIELDS: &[(&str, &str)] = &[
+    ("trainer", "full_name"),
+    ("trainer", "phone_number"),
+];
+
+/// Checks that every field `role` requires is present and not blank; `role` must
+/// come from `validate_role`. Each missing field gets its own field-level error,
+/// and the first missing field is returned along with them
+pub fn check_role_required_fields<'a>(
+    role: &str,
+    fields: impl IntoIterator<Item = (&'a str, Option<&'a str>)>,
+) -> Result<(), (&'static str, AppError)> {
+    let present: Vec<&str> = fields
+        .into_iter()
+        .filter(|(_, value)| value.is_some_and(|value| !value.trim().is_empty()))
+        .map(|(field, _)| field)
+        .collect();
+    let missing: Vec<&'static str> = ROLE_REQUIRED_FIELDS
+        .iter()
+        .filter(|(required_by, field)| *required_by == role && !present.contains(field))
+        .map(|(_, field)| *field)
+        .collect();
+    let Some(&first) = missing.first() else {
+        return Ok(());
+    };
+
+    let errors = missing
+        .iter()
+        .map(|field| FieldError::new(format!("/{}", field), format!("{} is required for {} accounts", field, role)))
+        .collect();
+    Err((first, AppError::FieldValidationError(errors)))
+}
+
+/// Validates one user field the way `POST /users` does; `None` for fields that have no validator
+pub fn validate_user_field(field: &str, value: &str, policy: &PasswordPolicy) -> Option<Result<(), AppError>> {
+    // Te same typy i walidatory, których używa POST /users, łącznie z normalizacją
+    let result = match field {
+        "username" => Username::parse(value).map(|_| ()),
+        "email" => Email::parse(value).map(|_| ()),
+        "password" => validate_password(value, policy),
+        "full_name" => validate_full_name(value),
+        "phone_number" => PhoneNumber::parse(value).map(|_| ()),
+        "role" => validate_role(value).map(|_| ()),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Validates the given fields, skipping absent ones, and maps each invalid
+/// field to its error messages
+pub fn validate_fields<'a>(
+    fields: impl IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    policy: &PasswordPolicy,
+) -> HashMap<String, Vec<String>> {
+    let mut errors: HashMap<String, Vec<String>> = HashMap::new();
+    for (field, value) in fields {
+        let Some(Err(e)) = value.and_then(|value| validate_user_field(field, value, policy)) else {
+            continue;
+        };
+        let message = match e {
+            AppError::ValidationError(message) => message,
+            other => other.to_string(),
+        };
+        errors.entry(field.to_string()).or_default().push(message);
+    }
+    errors
+}
+
+/// Runs every field validator on a registration payload and maps each invalid
+/// field to its error messages; an empty map means the input is valid
+pub fn validate_all(input: &CreateUserRequest, policy: &PasswordPolicy) -> HashMap<String, Vec<String>> {
+    let fields = [
+        ("username", Some(input.username.as_str())),
+        ("email", Some(input.email.as_str())),
+        ("password", Some(input.password.as_str())),
+        ("full_name", Some(input.full_name.as_str())),
+        ("phone_number", input.phone_number.as_deref()),
+        ("role", input.role.as_deref()),
+    ];
+    let mut errors = validate_fields(fields, policy);
+
+    // Wymagania zależne od roli sprawdzamy dopiero dla poprawnej roli
+    if let Ok(role) = input.role.as_deref().map(validate_role).transpose() {
+        push_role_required_errors(&mut errors, role.as_deref().unwrap_or("client"), fields);
     }
-}
\ No newline at end of file
+    errors
+}
+
+/// Adds the errors of `check_role_required_fields` to a field-to-errors map
+pub fn push_role_required_errors<'a>(
+    errors: &mut HashMap<String, Vec<String>>,
+    role: &str,
+    fields: impl IntoIterator<Item = (&'a str, Option<&'a str>)>,
+) {
+    if let Err((_, AppError::FieldValidationError(missing))) = check_role_required_fields(role, fields) {
+        for error in missing {
+            let field = error.pointer.trim_start_matches('/').to_string();
+            errors.entry(field).or_default().push(error.message);
+        }
+    }
+}
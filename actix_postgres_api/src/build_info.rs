@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// Build metadata embedded at compile time by `build.rs`
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub git_sha_short: &'static str,
+    pub build_timestamp: Option<DateTime<Utc>>,
+    pub rustc_version: &'static str,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("BUILD_GIT_SHA"),
+        git_sha_short: env!("BUILD_GIT_SHA_SHORT"),
+        build_timestamp: env!("BUILD_TIMESTAMP")
+            .parse()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+        rustc_version: env!("BUILD_RUSTC_VERSION"),
+    }
+}
+
+/// Build metadata together with the effective configuration, secrets redacted;
+/// logged at startup and served by `GET /version`
+pub fn startup_report(config: &Config) -> serde_json::Value {
+    serde_json::json!({
+        "build": build_info(),
+        "config": config,
+    })
+}
+
+/// Emits the startup event carrying the startup report
+pub fn log_startup(config: &Config) {
+    let report = startup_report(config);
+    tracing::info!(
+        target: "startup",
+        build = %report["build"],
+        config = %report["config"],
+        "Starting up application"
+    );
+}
@@ -0,0 +1,86 @@
+use actix_web::web::Bytes;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::monitoring::TRAINER_DIRECTORY_CACHE_COUNTER;
+
+/// How a cacheable response was served, reported in the `Cache-Status` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Bypass,
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+            Self::Bypass => "bypass",
+        }
+    }
+
+    fn record(&self) {
+        TRAINER_DIRECTORY_CACHE_COUNTER.with_label_values(&[self.as_str()]).inc();
+    }
+}
+
+// Klucz to znormalizowane (page, per_page) i filtr specjalizacji - katalog nie ma sortowania
+type PageKey = (u32, u32, Option<String>);
+
+/// Short-lived, in-process cache of serialized public trainer directory pages, shared by all workers
+pub struct TrainerDirectoryCache {
+    ttl: Duration,
+    pages: Mutex<HashMap<PageKey, (Instant, Bytes)>>,
+}
+
+impl TrainerDirectoryCache {
+    /// A zero `ttl` disables the cache; every lookup is then a bypass
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            pages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// Returns the cached page if it is still fresh, recording a hit or a miss
+    pub fn get(&self, page: u32, per_page: u32, specialty: Option<&str>) -> Option<Bytes> {
+        let pages = self.pages.lock().unwrap_or_else(|e| e.into_inner());
+        let cached = pages
+            .get(&(page, per_page, specialty.map(String::from)))
+            .filter(|(stored_at, _)| stored_at.elapsed() < self.ttl)
+            .map(|(_, body)| body.clone());
+
+        match cached {
+            Some(_) => CacheStatus::Hit.record(),
+            None => CacheStatus::Miss.record(),
+        }
+        cached
+    }
+
+    pub fn insert(&self, page: u32, per_page: u32, specialty: Option<&str>, body: Bytes) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut pages = self.pages.lock().unwrap_or_else(|e| e.into_inner());
+        // Przy okazji zapisu usuwamy przeterminowane strony, żeby mapa nie rosła bez końca
+        pages.retain(|_, (stored_at, _)| stored_at.elapsed() < self.ttl);
+        pages.insert((page, per_page, specialty.map(String::from)), (Instant::now(), body));
+    }
+
+    /// Records a lookup that skipped the cache
+    pub fn record_bypass(&self) {
+        CacheStatus::Bypass.record();
+    }
+
+    /// Drops every cached page; called whenever a user change may alter the directory
+    pub fn invalidate(&self) {
+        self.pages.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
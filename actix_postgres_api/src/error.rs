@@ -1,46 +1,201 @@
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{http::{header, StatusCode}, HttpResponse, ResponseError};
 use serde::Serialize;
 use sqlx::error::Error as SqlxError;
-use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum AppError {
     #[error("Database error: {0}")]
-    DatabaseError(#[from] SqlxError),
-    
+    DatabaseError(SqlxError),
+
+    // Baza chwilowo nieosiągalna lub pula połączeń wyczerpana - warto ponowić żądanie
+    #[error("Database unavailable: {0}")]
+    DatabaseUnavailable(SqlxError),
+
     #[error("Not found: {0}")]
     NotFoundError(String),
-    
+
     #[error("Validation error: {0}")]
     ValidationError(String),
-    
+
+    // Wiele naruszeń naraz, każde wskazuje pole przez JSON Pointer
+    #[error("Validation error: request body has {} invalid field(s)", .0.len())]
+    FieldValidationError(Vec<FieldError>),
+
+    // Treść żądania nie daje się sparsować lub zdeserializować
+    #[error("Malformed request body: {}", .0.message)]
+    MalformedBody(FieldError),
+
+    // Wartości o złym typie JSON - wszystkie naraz, z oczekiwanym typem
+    #[error("Malformed request body: {} field(s) have the wrong type", .0.len())]
+    TypeMismatch(Vec<FieldError>),
+
+    // Pola nieznane dla danego DTO (tylko w trybie strict)
+    #[error("Unknown field(s) in request body: {}", .0.join(", "))]
+    UnknownFields(Vec<String>),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    // Konto z hasłem tymczasowym po resecie; dozwolona jest tylko zmiana hasła
+    #[error("Password change required")]
+    PasswordChangeRequired,
+
+    // Zasób już istnieje i różni się od przesłanego
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    // Zbyt częste zmiany profilu; wartość to sugerowany czas oczekiwania w sekundach
+    #[error("Too many profile updates, retry in {0} seconds")]
+    WriteRateExceeded(u64),
+
+    // Wyczerpany limit żądań dla roli wywołującego; wartość to sugerowany czas oczekiwania w sekundach
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimitExceeded(u64),
+
+    // Wyczerpany limit prób (np. kodu weryfikacyjnego)
+    #[error("Too many attempts: {0}")]
+    TooManyAttempts(String),
+
+    // Funkcja wyłączona w tej instalacji (np. brak bramki SMS)
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
     #[error("Internal server error: {0}")]
     InternalServerError(String),
 }
 
+impl From<SqlxError> for AppError {
+    fn from(error: SqlxError) -> Self {
+        if is_connectivity_error(&error) {
+            AppError::DatabaseUnavailable(error)
+        } else {
+            AppError::DatabaseError(error)
+        }
+    }
+}
+
+// Błędy połączenia i puli, w odróżnieniu od błędów samego zapytania
+fn is_connectivity_error(error: &SqlxError) -> bool {
+    matches!(
+        error,
+        SqlxError::PoolTimedOut
+            | SqlxError::PoolClosed
+            | SqlxError::Io(_)
+            | SqlxError::Tls(_)
+            | SqlxError::WorkerCrashed
+    )
+}
+
+impl AppError {
+    /// Stable, machine-readable code returned alongside the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::DatabaseError(_) => "DATABASE_ERROR",
+            AppError::DatabaseUnavailable(_) => "DATABASE_UNAVAILABLE",
+            AppError::NotFoundError(_) => "NOT_FOUND",
+            AppError::ValidationError(_) => "VALIDATION_ERROR",
+            AppError::FieldValidationError(_) => "VALIDATION_ERROR",
+            AppError::MalformedBody(_) => "MALFORMED_BODY",
+            AppError::TypeMismatch(_) => "MALFORMED_BODY",
+            AppError::UnknownFields(_) => "UNKNOWN_FIELD",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::PasswordChangeRequired => "PASSWORD_CHANGE_REQUIRED",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::WriteRateExceeded(_) => "WRITE_RATE_EXCEEDED",
+            AppError::RateLimitExceeded(_) => "RATE_LIMIT_EXCEEDED",
+            AppError::TooManyAttempts(_) => "TOO_MANY_ATTEMPTS",
+            AppError::ServiceUnavailable(_) => "SERVICE_UNAVAILABLE",
+            AppError::InternalServerError(_) => "INTERNAL_ERROR",
+        }
+    }
+}
+
+// Pojedyncze naruszenie walidacji przypisane do konkretnego pola
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FieldError {
+    pub pointer: String,  // JSON Pointer, e.g. "/email"
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,  // Oczekiwany typ, jeśli błąd dotyczy typu
+}
+
+impl FieldError {
+    pub fn new(pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            pointer: pointer.into(),
+            message: message.into(),
+            expected: None,
+        }
+    }
+
+    pub fn with_expected(mut self, expected: impl Into<String>) -> Self {
+        self.expected = Some(expected.into());
+        self
+    }
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     status: String,
+    code: String,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>,
 }
 
 impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
         match self {
             AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::DatabaseUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::NotFoundError(_) => StatusCode::NOT_FOUND,
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AppError::FieldValidationError(_) => StatusCode::BAD_REQUEST,
+            AppError::MalformedBody(_) => StatusCode::BAD_REQUEST,
+            AppError::TypeMismatch(_) => StatusCode::BAD_REQUEST,
+            AppError::UnknownFields(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::PasswordChangeRequired => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::WriteRateExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::RateLimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         let status = self.status_code();
+        let errors = match self {
+            AppError::FieldValidationError(errors) => Some(errors.clone()),
+            AppError::MalformedBody(error) => Some(vec![error.clone()]),
+            AppError::TypeMismatch(errors) => Some(errors.clone()),
+            AppError::UnknownFields(pointers) => Some(
+                pointers
+                    .iter()
+                    .map(|pointer| FieldError::new(pointer.clone(), "Unknown field"))
+                    .collect(),
+            ),
+            _ => None,
+        };
         let error_response = ErrorResponse {
             status: status.to_string(),
+            code: self.code().to_string(),
             message: self.to_string(),
+            errors,
         };
-        HttpResponse::build(status).json(error_response)
+        let mut response = HttpResponse::build(status);
+        if let AppError::WriteRateExceeded(retry_after) | AppError::RateLimitExceeded(retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(error_response)
     }
-}
\ No newline at end of file
+}
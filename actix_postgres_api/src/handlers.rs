@@ -1,145 +1,445 @@
-use actix_web::{web, HttpResponse};
-use sqlx::{postgres::PgPool, types::Uuid};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use std::collections::HashMap;
+use sqlx::postgres::PgPool;
 use uuid::Uuid as UuidTrait;
 
-use crate::error::AppError;
-use crate::models::{CreateUserRequest, UpdateUserRequest, UserResponse, LoginRequest, LoginResponse, UserRole};
-use crate::repository::UserRepository;
-use crate::auth_utils::{
-    validate_password, validate_email, validate_phone_number, 
-    validate_username, validate_full_name, validate_role
+use crate::build_info::{build_info, startup_report};
+use crate::cache::{CacheStatus, TrainerDirectoryCache};
+use crate::config::{Config, DeletedIdentifierPolicy, MaintenanceLevel};
+use crate::error::{AppError, FieldError};
+use crate::middleware::{MaintenanceState, MaintenanceStatus};
+use crate::rate_limit::{client_address, WriteCaller, WriteRateLimiter};
+use crate::models::{
+    CreateUserRequest, UpdateUserRequest, UserResponse, LoginRequest, LoginResponse,
+    DuplicateGroup, DuplicatePair, DuplicateReport, MaintenanceRequest, PageQuery, BulkCreateQuery, PasswordCheckRequest, PasswordCheckResponse, PasswordResetResponse, PublicTrainerList, PublicTrainerProfile, NewUser, RankedUserResponse, RegistrationDraft, RegistrationValidationResponse, ReturnPreference, RoleAssignmentsQuery, RoleSpecificFields, TrainerDirectoryQuery, ValidateFieldRequest, VerifyPhoneRequest, ValidateFieldResponse, SearchQuery, User, UserChanges, BulkCreateUsersRequest, BulkCreateUsersResponse,
+    BulkCreatedRow, BulkMode, BulkRowError, BulkSummary, ChangePasswordRequest,
 };
+use crate::repository::{AuditLogRepository, FailedRequestRepository, Identifier, IdentifierStatus, InsertError, LoginError, SpecialtyRepository, UserRepository};
+use crate::normalize::TrimmedQuery;
+use crate::replica::{reads_own_writes, ReadPool};
+use crate::phone_verification::{send_phone_verification, verify_phone, SmsSender};
+use crate::schema::{deserialize_body, validate_against, RequestSchema, ValidatedJson};
+use crate::tokens::{bearer_token, decode_access_token, issue_access_token, AuthenticatedUser, PasswordChangeUser};
+use crate::types::{Email, ParseEnum, PhoneNumber, Username};
+use crate::auth_utils::{check_role_required_fields, constant_time_eq, enforce_role_fields, generate_password, hash_password, normalize_specialties, password_checklist, push_role_required_errors, validate_fields, validate_password, validate_full_name, validate_role, validate_user_field, verify_password};
 
-pub async fn get_all_users(db_pool: web::Data<PgPool>) -> Result<HttpResponse, AppError> {
-    let repo = UserRepository::new(db_pool.get_ref().clone());
+// Waliduje żądanie utworzenia użytkownika; błąd wskazuje pole, którego dotyczy.
+// Konto bez roli w żądaniu dostaje DEFAULT_ROLE
+fn build_new_user(user: CreateUserRequest, config: &Config) -> Result<NewUser, (&'static str, AppError)> {
+    // Identyfikator nadany przez klienta musi być poprawnym UUID
+    let id = user.id
+        .as_deref()
+        .map(|id| UuidTrait::parse_str(id)
+            .map_err(|_| ("id", AppError::ValidationError("Invalid UUID format".to_string()))))
+        .transpose()?;
+    
+    // Walidacja nazwy użytkownika
+    let username = Username::parse(&user.username).map_err(|e| ("username", e))?;
+    
+    // Walidacja adresu email
+    let email = Email::parse(&user.email).map_err(|e| ("email", e))?;
+    
+    // Walidacja pełnego imienia i nazwiska
+    validate_full_name(&user.full_name).map_err(|e| ("full_name", e))?;
+    
+    // Walidacja hasła
+    validate_password(&user.password, &config.password_policy).map_err(|e| ("password", e))?;
+    
+    // Validate phone number if provided
+    let phone_number = user.phone_number
+        .as_deref()
+        .map(PhoneNumber::parse)
+        .transpose()
+        .map_err(|e| ("phone_number", e))?;
+    
+    // Walidacja roli, jeśli podano
+    let role = user.role
+        .as_deref()
+        .map(validate_role)
+        .transpose()
+        .map_err(|e| ("role", e))?
+        .unwrap_or_else(|| config.default_role.clone());
+    
+    if let Some(expires_at) = user.role_expires_at {
+        validate_role_expiry(expires_at).map_err(|e| ("role_expires_at", e))?;
+    }
+    
+    // Pola wymagane przez rolę (np. telefon trenera), zgodnie z ROLE_REQUIRED_FIELDS
+    check_role_required_fields(
+        &role,
+        [
+            ("full_name", Some(user.full_name.as_str())),
+            ("phone_number", user.phone_number.as_deref()),
+        ],
+    )?;
+    
+    // Pola profilu innej roli są odrzucane lub pomijane, zależnie od konfiguracji
+    let mut role_fields = RoleSpecificFields {
+        bio: user.bio,
+        hourly_rate: user.hourly_rate,
+        fitness_goals: user.fitness_goals,
+        specialties: user.specialties.map(normalize_specialties),
+    };
+    enforce_role_fields(&role, &mut role_fields, config.role_field_policy)?;
+    
+    Ok(NewUser {
+        id,
+        username,
+        email,
+        password: user.password,
+        full_name: user.full_name,
+        phone_number,
+        role: Some(role),
+        role_expires_at: user.role_expires_at,
+        role_fields,
+    })
+}
+
+// Termin wygaśnięcia roli musi leżeć w przyszłości
+fn validate_role_expiry(expires_at: chrono::DateTime<Utc>) -> Result<(), AppError> {
+    if expires_at <= Utc::now() {
+        return Err(AppError::ValidationError("role_expires_at must be in the future".to_string()));
+    }
+    Ok(())
+}
+
+// Odrzuca identyfikator zajęty przez aktywne konto lub - przy polityce
+// "reserved" - przez konto usunięte
+async fn ensure_identifier_available(
+    repo: &UserRepository,
+    policy: DeletedIdentifierPolicy,
+    identifier: Identifier<'_>,
+    exclude_id: Option<UuidTrait>,
+) -> Result<(), AppError> {
+    let status = repo.identifier_status(identifier, exclude_id).await?;
+    match identifier_conflict(identifier, status, policy) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+fn identifier_conflict(
+    identifier: Identifier<'_>,
+    status: IdentifierStatus,
+    policy: DeletedIdentifierPolicy,
+) -> Option<AppError> {
+    let label = match identifier {
+        Identifier::Username(_) => "Username",
+        Identifier::Email(_) => "Email",
+    };
+    
+    match (status, policy) {
+        (IdentifierStatus::InUse, _) => {
+            Some(AppError::ValidationError(format!("{} is already in use", label)))
+        }
+        (IdentifierStatus::HeldByDeletedAccount, DeletedIdentifierPolicy::Reserved) => {
+            Some(AppError::ValidationError(format!(
+                "{} belongs to a deleted account and stays reserved (deleted accounts' identifiers are not reusable)",
+                label
+            )))
+        }
+        _ => None,
+    }
+}
+
+pub async fn get_all_users(db_pool: web::Data<PgPool>, read_pool: ReadPool) -> Result<HttpResponse, AppError> {
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
     let users = repo.find_all().await?;
     let response: Vec<UserResponse> = users.into_iter().map(UserResponse::from).collect();
     
     Ok(HttpResponse::Ok().json(response))
 }
 
+// Krótsze frazy nie mają pełnego trigramu i nie skorzystałyby z indeksu
+const MIN_SEARCH_QUERY_LENGTH: usize = 3;
+
+pub async fn search_users(
+    query: TrimmedQuery<SearchQuery>,
+    db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
+) -> Result<HttpResponse, AppError> {
+    let phrase = query.q.as_str();
+    if phrase.chars().count() < MIN_SEARCH_QUERY_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Search query must be at least {} characters long",
+            MIN_SEARCH_QUERY_LENGTH
+        )));
+    }
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
+    let users = repo.search(phrase).await?;
+    let response: Vec<UserResponse> = users.into_iter().map(UserResponse::from).collect();
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Jedno pole wyszukiwania dla administratorów; bez klucza administratora email nie jest przeszukiwany
+pub async fn search_users_ranked(
+    req: HttpRequest,
+    query: TrimmedQuery<SearchQuery>,
+    db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let phrase = query.q.as_str();
+    if phrase.chars().count() < MIN_SEARCH_QUERY_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Search query must be at least {} characters long",
+            MIN_SEARCH_QUERY_LENGTH
+        )));
+    }
+    let include_email = require_admin_key(&req, &config).is_ok();
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
+    let users = repo.search_ranked(phrase, include_email).await?;
+    let response: Vec<RankedUserResponse> = users
+        .into_iter()
+        .map(|user| RankedUserResponse::new(user, include_email))
+        .collect();
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
 pub async fn get_user_by_id(
     id: web::Path<String>,
     db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
 ) -> Result<HttpResponse, AppError> {
     let user_id = UuidTrait::parse_str(&id)
         .map_err(|_| AppError::ValidationError("Invalid UUID format".to_string()))?;
     
-    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
     let user = repo.find_by_id(user_id).await?;
     
     Ok(HttpResponse::Ok().json(UserResponse::from(user)))
 }
 
 pub async fn create_user(
-    user: web::Json<CreateUserRequest>,
+    user: ValidatedJson<CreateUserRequest>,
     db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, AppError> {
-    // Walidacja nazwy użytkownika
-    validate_username(&user.username)?;
+    let new_user = build_new_user(user.into_inner(), &config).map_err(|(_, e)| e)?;
     
-    // Walidacja adresu email
-    validate_email(&user.email)?;
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let specialties = SpecialtyRepository::new(db_pool.get_ref().clone());
+    if let Some(ref slugs) = new_user.role_fields.specialties {
+        ensure_specialties_known(&specialties, slugs).await?;
+    }
     
-    // Walidacja pełnego imienia i nazwiska
-    validate_full_name(&user.full_name)?;
+    // Ponowione żądanie z tym samym id zwraca istniejący zasób zamiast tworzyć nowy
+    if let Some(id) = new_user.id {
+        match repo.find_by_id(id).await {
+            Ok(existing) if same_user_data(&specialties, &existing, &new_user).await? => {
+                return Ok(HttpResponse::Ok().json(UserResponse::from(existing)));
+            }
+            Ok(_) => {
+                return Err(AppError::Conflict(format!("User {} already exists with different data", id)));
+            }
+            Err(AppError::NotFoundError(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
     
-    // Walidacja hasła
-    validate_password(&user.password)?;
+    // Sprawdź, czy email i nazwa użytkownika są wolne
+    let policy = config.deleted_identifier_policy;
+    ensure_identifier_available(&repo, policy, Identifier::Email(&new_user.email), None).await?;
+    ensure_identifier_available(&repo, policy, Identifier::Username(&new_user.username), None).await?;
     
-    // Validate phone number if provided
-    if let Some(ref phone) = user.phone_number {
-        validate_phone_number(phone)?;
-    }
+    let created_user = repo.create(new_user).await?;
     
-    // Walidacja roli, jeśli podano
-    let role = match &user.role {
-        Some(role) => Some(validate_role(role)?),
-        None => None
-    };
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, user_location(created_user.id)))
+        .json(UserResponse::from(created_user)))
+}
+
+// Adres zasobu użytkownika, zwracany w nagłówku Location
+fn user_location(id: UuidTrait) -> String {
+    format!("/api/users/{}", id)
+}
+
+// Etap kreatora rejestracji: pełna walidacja POST /users, łącznie z unikalnością,
+// ale bez zapisu czegokolwiek; sprawdzane są tylko przesłane pola
+pub async fn validate_registration(
+    draft: ValidatedJson<RegistrationDraft>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let draft = draft.into_inner();
+    let mut errors = validate_fields(
+        [
+            ("username", draft.username.as_deref()),
+            ("email", draft.email.as_deref()),
+            ("password", draft.password.as_deref()),
+            ("full_name", draft.full_name.as_deref()),
+            ("phone_number", draft.phone_number.as_deref()),
+            ("role", draft.role.as_deref()),
+        ],
+        &config.password_policy,
+    );
     
-    let repo = UserRepository::new(db_pool.get_ref().clone());
+    if draft.id.as_deref().is_some_and(|id| UuidTrait::parse_str(id).is_err()) {
+        errors.entry("id".to_string()).or_default().push("Invalid UUID format".to_string());
+    }
+    if let Some(Err(e)) = draft.role_expires_at.map(validate_role_expiry) {
+        errors.entry("role_expires_at".to_string()).or_default().push(row_error_message(e));
+    }
+    if let Ok(role) = draft.role.as_deref().map(validate_role).transpose() {
+        // Wymagania roli dotyczą etapu, który ją ustawia
+        if let Some(role) = role.as_deref() {
+            push_role_required_errors(
+                &mut errors,
+                role,
+                [("full_name", draft.full_name.as_deref()), ("phone_number", draft.phone_number.as_deref())],
+            );
+        }
+        let mut role_fields = RoleSpecificFields {
+            bio: draft.bio.clone(),
+            hourly_rate: draft.hourly_rate,
+            fitness_goals: draft.fitness_goals.clone(),
+            specialties: draft.specialties.clone().map(normalize_specialties),
+        };
+        if let Err((field, e)) = enforce_role_fields(role.as_deref().unwrap_or(&config.default_role), &mut role_fields, config.role_field_policy) {
+            errors.entry(field.to_string()).or_default().push(row_error_message(e));
+        }
+        if let Some(slugs) = role_fields.specialties.as_deref().filter(|_| !errors.contains_key("specialties")) {
+            let known = ensure_specialties_known(&SpecialtyRepository::new(db_pool.get_ref().clone()), slugs).await;
+            push_identifier_error(&mut errors, "specialties", known)?;
+        }
+    }
     
-    // Sprawdź, czy email już istnieje
-    let email_exists = sqlx::query!("SELECT COUNT(*) as count FROM users WHERE email = $1", user.email)
-        .fetch_one(db_pool.get_ref())
-        .await
-        .map_err(AppError::DatabaseError)?
-        .count
-        .unwrap_or(0) > 0;
-        
-    if email_exists {
-        return Err(AppError::ValidationError("Email is already in use".to_string()));
+    // Unikalność sprawdzamy tylko dla poprawnych wartości, tak jak create_user
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let policy = config.deleted_identifier_policy;
+    if let Some(email) = draft.email.as_deref().filter(|_| !errors.contains_key("email")) {
+        let email = Email::parse(email)?;
+        let available = ensure_identifier_available(&repo, policy, Identifier::Email(&email), None).await;
+        push_identifier_error(&mut errors, "email", available)?;
+    }
+    if let Some(username) = draft.username.as_deref().filter(|_| !errors.contains_key("username")) {
+        let username = Username::parse(username)?;
+        let available = ensure_identifier_available(&repo, policy, Identifier::Username(&username), None).await;
+        push_identifier_error(&mut errors, "username", available)?;
     }
     
-    // Sprawdź, czy nazwa użytkownika już istnieje
-    let username_exists = sqlx::query!("SELECT COUNT(*) as count FROM users WHERE username = $1", user.username)
-        .fetch_one(db_pool.get_ref())
-        .await
-        .map_err(AppError::DatabaseError)?
-        .count
-        .unwrap_or(0) > 0;
-        
-    if username_exists {
-        return Err(AppError::ValidationError("Username is already in use".to_string()));
+    Ok(HttpResponse::Ok().json(RegistrationValidationResponse { ok: errors.is_empty(), errors }))
+}
+
+// Zajęty identyfikator (lub inny błąd walidacji) trafia do mapy błędów; błędy bazy przerywają walidację
+fn push_identifier_error(
+    errors: &mut HashMap<String, Vec<String>>,
+    field: &str,
+    available: Result<(), AppError>,
+) -> Result<(), AppError> {
+    match available {
+        Ok(()) => Ok(()),
+        Err(AppError::ValidationError(message)) => {
+            errors.entry(field.to_string()).or_default().push(message);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Specjalizacje muszą pochodzić ze słownika; komunikat podaje dozwolone wartości
+async fn ensure_specialties_known(repo: &SpecialtyRepository, slugs: &[String]) -> Result<(), AppError> {
+    let unknown = repo.find_unknown(slugs).await?;
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let known: Vec<String> = repo.find_all().await?.into_iter().map(|specialty| specialty.slug).collect();
+    Err(AppError::ValidationError(format!(
+        "Unknown specialties: {}. Allowed values: {}",
+        unknown.join(", "),
+        known.join(", ")
+    )))
+}
+
+// Czy istniejący użytkownik odpowiada danym z żądania utworzenia
+async fn same_user_data(specialties: &SpecialtyRepository, existing: &User, new_user: &NewUser) -> Result<bool, AppError> {
+    let same_fields = existing.username == new_user.username.as_str()
+        && existing.email == new_user.email.as_str()
+        && existing.full_name == new_user.full_name
+        && existing.phone_number.as_deref() == new_user.phone_number.as_ref().map(|phone| phone.as_str())
+        && new_user.role.as_deref() == Some(existing.role.as_str())
+        && existing.bio == new_user.role_fields.bio
+        && existing.hourly_rate == new_user.role_fields.hourly_rate
+        && existing.fitness_goals == new_user.role_fields.fitness_goals;
+    if !same_fields {
+        return Ok(false);
     }
     
-    // Jeśli walidacja roli zmieniła jej wartość, stwórz nowy obiekt z zaktualizowaną rolą
-    let mut user_data = user.into_inner();
-    if let Some(validated_role) = role {
-        user_data.role = Some(validated_role);
+    let requested = new_user.role_fields.specialties.clone().unwrap_or_default();
+    if specialties.find_for_user(existing.id).await? != requested {
+        return Ok(false);
     }
     
-    let created_user = repo.create(user_data).await?;
+    // Hasło porównujemy z hashem tylko wtedy, gdy reszta danych się zgadza
+    verify_password(&new_user.password, &existing.password_hash).await
+}
+
+// Zapisy liczone są wywołującemu: właścicielowi ważnego tokenu, a bez tokenu adresowi klienta.
+// Powtarzające się przekroczenia trafiają do audit_log jako zdarzenie bezpieczeństwa
+async fn enforce_write_limit(
+    req: &HttpRequest,
+    config: &Config,
+    limiter: &WriteRateLimiter,
+    pool: &PgPool,
+    target_id: UuidTrait,
+) -> Result<(), AppError> {
+    let caller = bearer_token(req)
+        .and_then(|token| decode_access_token(token, &config.jwt_secret).ok())
+        .map(|claims| WriteCaller::User(claims.sub))
+        .unwrap_or_else(|| WriteCaller::Address(client_address(req)));
     
-    Ok(HttpResponse::Created().json(UserResponse::from(created_user)))
+    let Err(rejection) = limiter.check(&caller) else {
+        return Ok(());
+    };
+    if rejection.security_event {
+        tracing::warn!(target: "security", caller = %caller, "Profile write rate limit exceeded repeatedly within a day");
+        let details = serde_json::json!({ "caller": caller.to_string(), "user_id": target_id });
+        if let Err(e) = AuditLogRepository::new(pool.clone()).record("profile_write_rate_exceeded", &caller.to_string(), details).await {
+            tracing::error!("Failed to audit repeated profile write rate violations: {}", e);
+        }
+    }
+    Err(AppError::WriteRateExceeded(rejection.retry_after.as_secs_f64().ceil().max(1.0) as u64))
 }
 
 pub async fn update_user(
+    req: HttpRequest,
     id: web::Path<String>,
-    user: web::Json<UpdateUserRequest>,
+    user: ValidatedJson<UpdateUserRequest>,
     db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    write_limiter: web::Data<WriteRateLimiter>,
+    trainer_cache: web::Data<TrainerDirectoryCache>,
 ) -> Result<HttpResponse, AppError> {
     let user_id = UuidTrait::parse_str(&id)
         .map_err(|_| AppError::ValidationError("Invalid UUID format".to_string()))?;
     
+    let supplied = user.supplied_fields().len();
+    if config.max_update_fields > 0 && supplied > config.max_update_fields {
+        return Err(AppError::ValidationError(format!(
+            "An update may change at most {} fields, this one changes {}",
+            config.max_update_fields, supplied
+        )));
+    }
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let policy = config.deleted_identifier_policy;
+    
     // Walidacja danych wejściowych
-    if let Some(ref email) = user.email {
-        validate_email(email)?;
-        
+    let email = user.email.as_deref().map(Email::parse).transpose()?;
+    if let Some(ref email) = email {
         // Sprawdź, czy nowy email nie koliduje z istniejącym
-        let email_exists = sqlx::query!(
-            "SELECT COUNT(*) as count FROM users WHERE email = $1 AND id != $2", 
-            email, user_id
-        )
-        .fetch_one(db_pool.get_ref())
-        .await
-        .map_err(AppError::DatabaseError)?
-        .count
-        .unwrap_or(0) > 0;
-            
-        if email_exists {
-            return Err(AppError::ValidationError("Email is already in use".to_string()));
-        }
+        ensure_identifier_available(&repo, policy, Identifier::Email(email), Some(user_id)).await?;
     }
     
-    if let Some(ref username) = user.username {
-        validate_username(username)?;
-        
+    let username = user.username.as_deref().map(Username::parse).transpose()?;
+    if let Some(ref username) = username {
         // Sprawdź, czy nowa nazwa użytkownika nie koliduje z istniejącą
-        let username_exists = sqlx::query!(
-            "SELECT COUNT(*) as count FROM users WHERE username = $1 AND id != $2", 
-            username, user_id
-        )
-        .fetch_one(db_pool.get_ref())
-        .await
-        .map_err(AppError::DatabaseError)?
-        .count
-        .unwrap_or(0) > 0;
-            
-        if username_exists {
-            return Err(AppError::ValidationError("Username is already in use".to_string()));
-        }
+        ensure_identifier_available(&repo, policy, Identifier::Username(username), Some(user_id)).await?;
     }
     
     if let Some(ref full_name) = user.full_name {
@@ -148,22 +448,63 @@ pub async fn update_user(
     
     // Walidacja hasła, jeśli jest aktualizowane
     if let Some(ref password) = user.password {
-        validate_password(password)?;
+        validate_password(password, &config.password_policy)?;
     }
     
-    // Validate phone number if provided
-    if let Some(ref phone) = user.phone_number {
-        validate_phone_number(phone)?;
+    // Validate phone number if provided; an explicit null removes it
+    let phone_number = user
+        .phone_number
+        .as_ref()
+        .map(|phone| phone.as_deref().map(PhoneNumber::parse).transpose())
+        .transpose()?;
+    
+    // Rola jest sprawdzana już przy deserializacji (ParseEnum)
+    let role = user.role.map(|role| role.as_str().to_string());
+    
+    // Nowa rola bez terminu jest bezterminowa; sam termin przedłuża bieżącą rolę
+    if let Some(expires_at) = user.role_expires_at {
+        validate_role_expiry(expires_at)?;
     }
+    let role_expires_at = match (&role, user.role_expires_at) {
+        (Some(_), expires_at) => Some(expires_at),
+        (None, Some(expires_at)) => Some(Some(expires_at)),
+        (None, None) => None,
+    };
     
-    // Walidacja roli, jeśli jest aktualizowana
-    let mut user_data = user.into_inner();
-    if let Some(ref role) = user_data.role {
-        user_data.role = Some(validate_role(role)?);
+    let user_data = user.into_inner();
+    
+    // Pola profilu sprawdza repozytorium, względem roli zablokowanego wiersza
+    let role_fields = RoleSpecificFields {
+        bio: user_data.bio,
+        hourly_rate: user_data.hourly_rate,
+        fitness_goals: user_data.fitness_goals,
+        specialties: user_data.specialties.map(normalize_specialties),
+    };
+    if let Some(ref slugs) = role_fields.specialties {
+        ensure_specialties_known(&SpecialtyRepository::new(db_pool.get_ref().clone()), slugs).await?;
     }
     
-    let repo = UserRepository::new(db_pool.get_ref().clone());
-    let updated_user = repo.update(user_id, user_data).await?;
+    let changes = UserChanges {
+        username,
+        email,
+        password: user_data.password,
+        full_name: user_data.full_name,
+        phone_number,
+        active: user_data.active,
+        role,
+        role_expires_at,
+        public_profile: user_data.public_profile,
+        role_fields,
+    };
+    
+    // Limit zmian profilu liczony dopiero po walidacji, żeby błędne żądania go nie zużywały;
+    // żądania z kluczem administratora go omijają
+    if require_admin_key(&req, &config).is_err() {
+        enforce_write_limit(&req, &config, &write_limiter, db_pool.get_ref(), user_id).await?;
+    }
+    
+    let updated_user = repo.update(user_id, changes, config.role_field_policy).await?;
+    trainer_cache.invalidate();
     
     Ok(HttpResponse::Ok().json(UserResponse::from(updated_user)))
 }
@@ -171,22 +512,25 @@ pub async fn update_user(
 pub async fn delete_user(
     id: web::Path<String>,
     db_pool: web::Data<PgPool>,
+    trainer_cache: web::Data<TrainerDirectoryCache>,
 ) -> Result<HttpResponse, AppError> {
     let user_id = UuidTrait::parse_str(&id)
         .map_err(|_| AppError::ValidationError("Invalid UUID format".to_string()))?;
     
     let repo = UserRepository::new(db_pool.get_ref().clone());
     repo.delete(user_id).await?;
+    trainer_cache.invalidate();
     
     Ok(HttpResponse::NoContent().finish())
 }
 
 pub async fn login(
-    login: web::Json<LoginRequest>,
+    login: ValidatedJson<LoginRequest>,
     db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, AppError> {
     // Walidacja danych logowania
-    validate_email(&login.email)?;
+    let email = Email::parse(&login.email)?;
     
     if login.password.is_empty() {
         return Err(AppError::ValidationError("Password cannot be empty".to_string()));
@@ -195,13 +539,739 @@ pub async fn login(
     let repo = UserRepository::new(db_pool.get_ref().clone());
     
     // Authenticate user
-    let user = repo.authenticate(login.into_inner()).await?;
+    let user = repo
+        .authenticate(&email, &login.password, &config.lockout_policy)
+        .await
+        .map_err(LoginError::into_app_error)?;
+    
+    let access_token = issue_access_token(&user, &config.jwt_secret, config.access_token_ttl_secs)?;
     
     // Create success response
     let response = LoginResponse {
         user: UserResponse::from(user),
         message: "Login successful".to_string(),
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: config.access_token_ttl_secs,
+    };
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Sprawdza jedno pole formularza rejestracji, zanim klient wyśle cały formularz
+pub async fn validate_field(
+    body: ValidatedJson<ValidateFieldRequest>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let request = body.into_inner();
+    
+    // Te same walidatory, których używa POST /users
+    let result = validate_user_field(&request.field, &request.value, &config.password_policy)
+        .ok_or_else(|| AppError::ValidationError(format!("Unknown field '{}'", request.field)))?;
+    
+    let response = match result {
+        Ok(()) => ValidateFieldResponse { valid: true, message: None, code: None },
+        Err(e) => ValidateFieldResponse {
+            valid: false,
+            code: Some(e.code()),
+            message: Some(row_error_message(e)),
+        },
+    };
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Publiczne profile zmieniają się rzadko, więc mogą być długo cache'owane przez CDN
+const PUBLIC_PROFILE_CACHE_CONTROL: &str = "public, max-age=300, stale-while-revalidate=600";
+
+pub async fn get_public_trainers(
+    req: HttpRequest,
+    query: TrimmedQuery<TrainerDirectoryQuery>,
+    db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
+    cache: web::Data<TrainerDirectoryCache>,
+) -> Result<HttpResponse, AppError> {
+    let (page, per_page) = page_bounds(&PageQuery { page: query.page, per_page: query.per_page })?;
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
+    
+    // Filtr musi być znaną specjalizacją, tak samo jak przy zapisie
+    let specialty = query.specialty.as_deref().map(str::to_lowercase);
+    if let Some(ref slug) = specialty {
+        ensure_specialties_known(&SpecialtyRepository::new(db_pool.get_ref().clone()), std::slice::from_ref(slug)).await?;
+    }
+    let specialty = specialty.as_deref();
+    
+    // Cache-Control: no-cache od klienta pomija cache, co ułatwia debugowanie;
+    // X-Read-Your-Writes też, bo strona z cache mogła powstać przed zapisem klienta
+    let no_cache = reads_own_writes(&req)
+        || req
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("no-cache"));
+    
+    let (body, status) = if no_cache || !cache.is_enabled() {
+        cache.record_bypass();
+        (load_trainer_page(&repo, page, per_page, specialty).await?, CacheStatus::Bypass)
+    } else if let Some(body) = cache.get(page, per_page, specialty) {
+        (body, CacheStatus::Hit)
+    } else {
+        let body = load_trainer_page(&repo, page, per_page, specialty).await?;
+        cache.insert(page, per_page, specialty, body.clone());
+        (body, CacheStatus::Miss)
+    };
+    
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, PUBLIC_PROFILE_CACHE_CONTROL))
+        .insert_header(("Cache-Status", status.as_str()))
+        .content_type("application/json")
+        .body(body))
+}
+
+// Strona katalogu trenerów serializowana od razu, w postaci gotowej do cache'owania
+async fn load_trainer_page(
+    repo: &UserRepository,
+    page: u32,
+    per_page: u32,
+    specialty: Option<&str>,
+) -> Result<web::Bytes, AppError> {
+    let (trainers, total) = repo
+        .find_public_trainers(per_page as i64, ((page - 1) * per_page) as i64, specialty)
+        .await?;
+    
+    let list = PublicTrainerList {
+        trainers: trainers.into_iter().map(PublicTrainerProfile::from).collect(),
+        page,
+        per_page,
+        total,
+    };
+    serde_json::to_vec(&list)
+        .map(web::Bytes::from)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to serialize trainer directory: {}", e)))
+}
+
+/// Pre-populates the first `pages` unfiltered pages of the trainer directory at the default page size
+pub async fn warm_trainer_directory(
+    repo: &UserRepository,
+    cache: &TrainerDirectoryCache,
+    pages: u32,
+) -> Result<(), AppError> {
+    if !cache.is_enabled() {
+        return Ok(());
+    }
+    for page in 1..=pages {
+        let body = load_trainer_page(repo, page, DEFAULT_PER_PAGE, None).await?;
+        cache.insert(page, DEFAULT_PER_PAGE, None, body);
+    }
+    Ok(())
+}
+
+pub async fn get_public_trainer(
+    username: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
+) -> Result<HttpResponse, AppError> {
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
+    let trainer = repo.find_public_trainer(&username).await?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, PUBLIC_PROFILE_CACHE_CONTROL))
+        .json(PublicTrainerProfile::from(trainer)))
+}
+
+// Słownik specjalizacji, np. do listy wyboru w formularzu trenera
+pub async fn get_specialties(db_pool: web::Data<PgPool>) -> Result<HttpResponse, AppError> {
+    let specialties = SpecialtyRepository::new(db_pool.get_ref().clone()).find_all().await?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, PUBLIC_PROFILE_CACHE_CONTROL))
+        .json(specialties))
+}
+
+// Publikuje obowiązującą politykę haseł, aby klienci walidowali hasła tak samo jak serwer
+pub async fn get_password_policy(config: web::Data<Config>) -> HttpResponse {
+    HttpResponse::Ok().json(&config.password_policy)
+}
+
+// Lista kontrolna wymagań dla formularza; hasło nie jest nigdzie zapisywane ani logowane
+pub async fn check_password(
+    body: ValidatedJson<PasswordCheckRequest>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let requirements = password_checklist(&body.password, &config.password_policy);
+    let acceptable = validate_password(&body.password, &config.password_policy).is_ok();
+    
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(PasswordCheckResponse { acceptable, requirements })
+}
+
+pub async fn get_me(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().json(UserResponse::from(user.0))
+}
+
+pub async fn send_phone_code(
+    user: AuthenticatedUser,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    sms_sender: web::Data<dyn SmsSender>,
+) -> Result<HttpResponse, AppError> {
+    send_phone_verification(db_pool.get_ref(), sms_sender.get_ref(), &config, user.0.id).await?;
+    
+    Ok(HttpResponse::Accepted().finish())
+}
+
+pub async fn confirm_phone(
+    user: AuthenticatedUser,
+    body: web::Json<VerifyPhoneRequest>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let verified = verify_phone(db_pool.get_ref(), &config, user.0.id, &body.code).await?;
+    
+    Ok(HttpResponse::Ok().json(UserResponse::from(verified)))
+}
+
+// Zmiana własnego hasła; jedyny endpoint dostępny z hasłem tymczasowym po resecie
+pub async fn change_password(
+    user: PasswordChangeUser,
+    body: ValidatedJson<ChangePasswordRequest>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    if !verify_password(&body.current_password, &user.0.password_hash).await? {
+        return Err(AppError::ValidationError("Current password is incorrect".to_string()));
+    }
+    validate_password(&body.new_password, &config.password_policy)?;
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let password_hash = hash_password(&body.new_password).await?;
+    let updated = repo.change_password(user.0.id, &password_hash).await?;
+    tracing::info!("User {} changed their password", user.0.id);
+    
+    // Zmiana hasła unieważnia dotychczasowe tokeny, więc bieżąca sesja dostaje nowy
+    let access_token = issue_access_token(&updated, &config.jwt_secret, config.access_token_ttl_secs)?;
+    let response = LoginResponse {
+        user: UserResponse::from(updated),
+        message: "Password changed".to_string(),
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: config.access_token_ttl_secs,
+    };
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Unieważnia wszystkie tokeny użytkownika, łącznie z tym użytym w żądaniu
+pub async fn logout_all(
+    user: AuthenticatedUser,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    repo.bump_token_version(user.0.id).await?;
+    tracing::info!("All sessions revoked for user {}", user.0.id);
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Endpoint to expose application health status
+pub async fn health_check(maintenance: Option<web::Data<MaintenanceState>>) -> HttpResponse {
+    let maintenance = maintenance
+        .map(|state| state.status())
+        .unwrap_or(MaintenanceStatus { mode: MaintenanceLevel::Off, message: None, ends_at: None });
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "up",
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": build_info().git_sha_short,
+        "maintenance": maintenance
+    }))
+}
+
+pub async fn get_version(
+    req: HttpRequest,
+    user: Option<AuthenticatedUser>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    // Z VERSION_REQUIRES_AUTH wystarcza ważny token dostępu albo klucz administratora
+    if config.version_requires_auth && user.is_none() && require_admin_key(&req, &config).is_err() {
+        return Err(AppError::Unauthorized("Authentication required".to_string()));
+    }
+    
+    Ok(HttpResponse::Ok().json(startup_report(&config)))
+}
+
+// Sprawdza nagłówek X-Admin-Key względem klucza z konfiguracji
+pub(crate) fn require_admin_key(req: &HttpRequest, config: &Config) -> Result<(), AppError> {
+    let provided = req
+        .headers()
+        .get("X-Admin-Key")
+        .and_then(|value| value.to_str().ok());
+
+    match (&config.admin_api_key, provided) {
+        (Some(expected), Some(provided))
+            if !expected.is_empty() && constant_time_eq(expected.as_bytes(), provided.as_bytes()) => Ok(()),
+        _ => Err(AppError::Forbidden("A valid admin key is required".to_string())),
+    }
+}
+
+pub async fn get_maintenance_mode(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    state: web::Data<MaintenanceState>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+
+    Ok(HttpResponse::Ok().json(state.status()))
+}
+
+pub async fn set_maintenance_mode(
+    req: HttpRequest,
+    body: ValidatedJson<MaintenanceRequest>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    state: web::Data<MaintenanceState>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+
+    let body = body.into_inner();
+    let mode = match (body.mode, body.enabled) {
+        (Some(mode), _) => mode,
+        (None, Some(true)) => MaintenanceLevel::Full,
+        (None, Some(false)) => MaintenanceLevel::Off,
+        (None, None) => {
+            return Err(AppError::ValidationError("Either mode or enabled must be provided".to_string()));
+        }
     };
+    if body.ends_at.is_some_and(|ends_at| ends_at <= Utc::now()) {
+        return Err(AppError::ValidationError("ends_at must be in the future".to_string()));
+    }
+
+    let status = MaintenanceStatus {
+        mode,
+        message: body.message,
+        ends_at: body.ends_at,
+    };
+
+    // Zmiana trybu obowiązuje od razu - przełącznik musi działać także wtedy,
+    // gdy baza jest niedostępna, więc błąd zapisu do dziennika jest tylko logowany
+    let previous = state.status();
+    state.set(status.clone());
+    tracing::warn!("Maintenance mode changed from {:?} to {:?}", previous.mode, mode);
+
+    if let Err(e) = AuditLogRepository::new(db_pool.get_ref().clone())
+        .record(
+            "maintenance_mode_changed",
+            "admin",
+            serde_json::json!({ "from": previous, "to": status }),
+        )
+        .await
+    {
+        tracing::error!("Failed to record maintenance mode change in audit log: {}", e);
+    }
+
+    Ok(HttpResponse::Ok().json(state.status()))
+}
+
+pub async fn get_failed_request(
+    req: HttpRequest,
+    request_id: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+
+    let request_id = UuidTrait::parse_str(&request_id)
+        .map_err(|_| AppError::ValidationError("Invalid UUID format".to_string()))?;
+
+    let repo = FailedRequestRepository::new(db_pool.get_ref().clone());
+    let failed = repo.find_by_request_id(request_id).await?;
+
+    Ok(HttpResponse::Ok().json(failed))
+}
+
+pub async fn get_role_assignments(
+    req: HttpRequest,
+    query: TrimmedQuery<RoleAssignmentsQuery>,
+    db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
+    let users = repo.find_role_assignments(query.expiring_before).await?;
+    let response: Vec<UserResponse> = users.into_iter().map(UserResponse::from).collect();
     
     Ok(HttpResponse::Ok().json(response))
-}
\ No newline at end of file
+}
+
+const DEFAULT_PER_PAGE: u32 = 20;
+const MAX_PER_PAGE: u32 = 100;
+
+// Zwraca (page, per_page) z wartościami domyślnymi
+fn page_bounds(query: &PageQuery) -> Result<(u32, u32), AppError> {
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page == 0 || per_page == 0 || per_page > MAX_PER_PAGE {
+        return Err(AppError::ValidationError(format!(
+            "page must be at least 1 and per_page between 1 and {}",
+            MAX_PER_PAGE
+        )));
+    }
+    Ok((page, per_page))
+}
+
+// Grupa kandydatów przed dołączeniem danych kont
+struct CandidateGroup {
+    ids: Vec<UuidTrait>,
+    confidence: f32,
+    reasons: Vec<&'static str>,
+}
+
+// Łączy pary w grupy (spójne składowe), tak aby A~B i B~C trafiły do jednej grupy
+fn group_duplicate_pairs(pairs: &[DuplicatePair], config: &Config) -> Vec<CandidateGroup> {
+    let mut group_of: HashMap<UuidTrait, usize> = HashMap::new();
+    let mut groups: Vec<Option<CandidateGroup>> = Vec::new();
+    
+    for pair in pairs {
+        let mut reasons = Vec::new();
+        let mut confidence: f32 = 0.0;
+        if pair.phone_match {
+            reasons.push("phone_number");
+            confidence = 1.0;
+        }
+        if pair.email_similarity >= config.duplicate_email_similarity {
+            reasons.push("email");
+            confidence = confidence.max(pair.email_similarity);
+        }
+        if pair.name_similarity >= config.duplicate_name_similarity {
+            reasons.push("full_name");
+            confidence = confidence.max(pair.name_similarity);
+        }
+        
+        let target = match (group_of.get(&pair.first_id).copied(), group_of.get(&pair.second_id).copied()) {
+            (Some(first), Some(second)) if first != second => {
+                // Para łączy dwie istniejące grupy - przenosimy drugą do pierwszej
+                let absorbed = groups[second].take().expect("group index points at a live group");
+                for id in &absorbed.ids {
+                    group_of.insert(*id, first);
+                }
+                let group = groups[first].as_mut().expect("group index points at a live group");
+                group.ids.extend(absorbed.ids);
+                group.confidence = group.confidence.max(absorbed.confidence);
+                group.reasons.extend(absorbed.reasons);
+                first
+            }
+            (Some(index), _) | (_, Some(index)) => index,
+            (None, None) => {
+                groups.push(Some(CandidateGroup { ids: Vec::new(), confidence: 0.0, reasons: Vec::new() }));
+                groups.len() - 1
+            }
+        };
+        
+        let group = groups[target].as_mut().expect("group index points at a live group");
+        for id in [pair.first_id, pair.second_id] {
+            if group_of.insert(id, target).is_none() {
+                group.ids.push(id);
+            }
+        }
+        group.confidence = group.confidence.max(confidence);
+        group.reasons.extend(reasons);
+    }
+    
+    let mut groups: Vec<CandidateGroup> = groups.into_iter().flatten().collect();
+    for group in &mut groups {
+        group.ids.sort();
+        group.reasons.sort();
+        group.reasons.dedup();
+        group.confidence = (group.confidence * 100.0).round() / 100.0;
+    }
+    // Najpewniejsze grupy najpierw; id zapewnia stabilną kolejność między stronami
+    groups.sort_by(|a, b| b.confidence.total_cmp(&a.confidence).then_with(|| a.ids.cmp(&b.ids)));
+    groups
+}
+
+pub async fn get_user_duplicates(
+    req: HttpRequest,
+    query: TrimmedQuery<PageQuery>,
+    db_pool: web::Data<PgPool>,
+    read_pool: ReadPool,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+    
+    let (page, per_page) = page_bounds(&query)?;
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
+    let pairs = repo
+        .find_duplicate_pairs(config.duplicate_email_similarity, config.duplicate_name_similarity)
+        .await?;
+    let groups = group_duplicate_pairs(&pairs, &config);
+    let total = groups.len();
+    
+    let page_groups: Vec<CandidateGroup> = groups
+        .into_iter()
+        .skip(((page - 1) * per_page) as usize)
+        .take(per_page as usize)
+        .collect();
+    
+    // Dane kont pobieramy tylko dla grup z bieżącej strony
+    let ids: Vec<UuidTrait> = page_groups.iter().flat_map(|group| group.ids.iter().copied()).collect();
+    let mut users: HashMap<UuidTrait, User> = repo
+        .find_by_ids(&ids)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+    
+    let groups = page_groups
+        .into_iter()
+        .map(|group| DuplicateGroup {
+            confidence: group.confidence,
+            reasons: group.reasons,
+            users: group.ids
+                .iter()
+                .filter_map(|id| users.remove(id))
+                .map(UserResponse::from)
+                .collect(),
+        })
+        .collect();
+    
+    Ok(HttpResponse::Ok().json(DuplicateReport { groups, page, per_page, total }))
+}
+
+pub async fn merge_users(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    trainer_cache: web::Data<TrainerDirectoryCache>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+    
+    let (keep_id, merge_id) = path.into_inner();
+    let parse = |id: &str| UuidTrait::parse_str(id)
+        .map_err(|_| AppError::ValidationError("Invalid UUID format".to_string()));
+    let keep_id = parse(&keep_id)?;
+    let merge_id = parse(&merge_id)?;
+    if keep_id == merge_id {
+        return Err(AppError::ValidationError("An account cannot be merged into itself".to_string()));
+    }
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let kept = repo.merge(keep_id, merge_id).await?;
+    trainer_cache.invalidate();
+    
+    Ok(HttpResponse::Ok().json(UserResponse::from(kept)))
+}
+
+// Reset hasła dla użytkownika, który nie może się zalogować; hasło tymczasowe
+// nie jest nigdzie zapisywane, więc pokazujemy je tylko w tej odpowiedzi
+pub async fn reset_user_password(
+    req: HttpRequest,
+    id: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    require_admin_key(&req, &config)?;
+    
+    let user_id = UuidTrait::parse_str(&id)
+        .map_err(|_| AppError::ValidationError("Invalid UUID format".to_string()))?;
+    
+    let temporary_password = generate_password(&config.password_policy)?;
+    let password_hash = hash_password(&temporary_password).await?;
+    
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let user = repo.force_password_reset(user_id, &password_hash).await?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(PasswordResetResponse { user: UserResponse::from(user), temporary_password }))
+}
+
+// Komunikat błędu bez prefiksu wariantu, do raportów per wiersz
+fn row_error_message(error: AppError) -> String {
+    match error {
+        AppError::ValidationError(message) | AppError::Conflict(message) | AppError::Forbidden(message) => message,
+        other => other.to_string(),
+    }
+}
+
+// Waliduje pojedynczy wiersz żądania masowego tak samo jak POST /users
+async fn validate_bulk_row(
+    repo: &UserRepository,
+    specialties: &SpecialtyRepository,
+    config: &Config,
+    index: usize,
+    row: serde_json::Value,
+) -> Result<NewUser, Vec<BulkRowError>> {
+    let row_error = |field: &str, error: AppError| BulkRowError {
+        index,
+        field: field.to_string(),
+        code: error.code(),
+        message: row_error_message(error),
+    };
+    let from_field_errors = |code: &'static str, errors: Vec<FieldError>| {
+        errors
+            .into_iter()
+            .map(|e| BulkRowError {
+                index,
+                field: e.pointer.trim_start_matches('/').to_string(),
+                code,
+                message: e.message,
+            })
+            .collect::<Vec<_>>()
+    };
+    
+    let request = match validate_against(CreateUserRequest::schema(), &row)
+        .and_then(|_| deserialize_body::<CreateUserRequest>(row))
+    {
+        Ok((request, _)) => request,
+        Err(AppError::FieldValidationError(errors)) => return Err(from_field_errors("VALIDATION_ERROR", errors)),
+        Err(AppError::MalformedBody(error)) => return Err(from_field_errors("MALFORMED_BODY", vec![error])),
+        Err(AppError::TypeMismatch(errors)) => return Err(from_field_errors("MALFORMED_BODY", errors)),
+        Err(e) => return Err(vec![row_error("", e)]),
+    };
+    
+    let new_user = build_new_user(request, config)
+        .map_err(|(field, e)| match e {
+            AppError::FieldValidationError(errors) => from_field_errors("VALIDATION_ERROR", errors),
+            e => vec![row_error(field, e)],
+        })?;
+    
+    if let Some(ref slugs) = new_user.role_fields.specialties {
+        match ensure_specialties_known(specialties, slugs).await {
+            Ok(()) => {}
+            Err(e @ AppError::ValidationError(_)) => return Err(vec![row_error("specialties", e)]),
+            Err(e) => return Err(vec![row_error("", e)]),
+        }
+    }
+    
+    // Konflikty z aktywnymi kontami (także w obrębie partii) zgłasza indeks unikalny
+    // przy zapisie; tutaj odrzucamy tylko identyfikatory zarezerwowane po usuniętych kontach
+    let policy = config.deleted_identifier_policy;
+    if policy == DeletedIdentifierPolicy::Reserved {
+        for (field, identifier) in [
+            ("username", Identifier::Username(&new_user.username)),
+            ("email", Identifier::Email(&new_user.email)),
+        ] {
+            let status = repo.identifier_status(identifier, None).await
+                .map_err(|e| vec![row_error(field, e)])?;
+            if status == IdentifierStatus::HeldByDeletedAccount {
+                if let Some(e) = identifier_conflict(identifier, status, policy) {
+                    return Err(vec![row_error(field, e)]);
+                }
+            }
+        }
+    }
+    
+    Ok(new_user)
+}
+
+fn insert_error_to_row_error(index: usize, error: InsertError) -> BulkRowError {
+    let field = match error {
+        InsertError::UniqueViolation { field } => field,
+        InsertError::Other(_) => "",
+    };
+    let error = error.into_app_error();
+    BulkRowError {
+        index,
+        field: field.to_string(),
+        code: error.code(),
+        message: row_error_message(error),
+    }
+}
+
+pub async fn bulk_create_users(
+    body: ValidatedJson<BulkCreateUsersRequest>,
+    query: TrimmedQuery<BulkCreateQuery>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let request = body.into_inner();
+    let mode = request.mode.unwrap_or_default();
+    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let specialties = SpecialtyRepository::new(db_pool.get_ref().clone());
+    
+    let return_preference = query.return_preference.unwrap_or_default();
+    let created_row = |index: usize, user: User| BulkCreatedRow {
+        index,
+        id: user.id,
+        user: (return_preference == ReturnPreference::Representation).then(|| UserResponse::from(user)),
+    };
+    let preference_applied = ("Preference-Applied", format!("return={}", return_preference.as_str()));
+    
+    // Walidacja wszystkich wierszy przed jakimkolwiek zapisem
+    let total = request.users.len();
+    let mut valid_rows = Vec::new();
+    let mut errors = Vec::new();
+    for (index, row) in request.users.into_iter().enumerate() {
+        match validate_bulk_row(&repo, &specialties, &config, index, row).await {
+            Ok(new_user) => valid_rows.push((index, new_user)),
+            Err(row_errors) => errors.extend(row_errors),
+        }
+    }
+    
+    let (indices, new_users): (Vec<usize>, Vec<NewUser>) = valid_rows.into_iter().unzip();
+    
+    match mode {
+        BulkMode::Atomic => {
+            // W trybie atomowym każdy błąd odrzuca całą partię; odpowiedź ma ten sam kształt
+            // co w trybie per_row, ze zbiorczym podsumowaniem, ale nic nie zostaje zapisane
+            let rejected = |mut errors: Vec<BulkRowError>| {
+                let mut summary = BulkSummary::new(total, 0, &errors);
+                if let Some(max_errors) = query.max_errors {
+                    summary.errors_truncated = errors.len() > max_errors;
+                    errors.truncate(max_errors);
+                }
+                HttpResponse::BadRequest()
+                    .insert_header(preference_applied.clone())
+                    .json(BulkCreateUsersResponse { summary, created: Vec::new(), errors })
+            };
+            if !errors.is_empty() {
+                return Ok(rejected(errors));
+            }
+            
+            let created = match repo.create_all(new_users).await {
+                Ok(created) => created,
+                Err((position, e)) => return Ok(rejected(vec![insert_error_to_row_error(indices[position], e)])),
+            };
+            
+            let created = indices
+                .into_iter()
+                .zip(created)
+                .map(|(index, user)| created_row(index, user))
+                .collect::<Vec<_>>();
+            let summary = BulkSummary::new(total, created.len(), &[]);
+            Ok(HttpResponse::Created()
+                .insert_header(preference_applied)
+                .json(BulkCreateUsersResponse { summary, created, errors: Vec::new() }))
+        }
+        BulkMode::PerRow => {
+            let mut created = Vec::new();
+            for (index, result) in indices.into_iter().zip(repo.create_each(new_users).await) {
+                match result {
+                    Ok(user) => created.push(created_row(index, user)),
+                    Err(e) => errors.push(insert_error_to_row_error(index, e)),
+                }
+            }
+            errors.sort_by_key(|e| e.index);
+            
+            let mut summary = BulkSummary::new(total, created.len(), &errors);
+            if let Some(max_errors) = query.max_errors {
+                summary.errors_truncated = errors.len() > max_errors;
+                errors.truncate(max_errors);
+            }
+            
+            let mut builder = if summary.invalid == 0 {
+                HttpResponse::Created()
+            } else {
+                HttpResponse::MultiStatus()
+            };
+            Ok(builder
+                .insert_header(preference_applied)
+                .json(BulkCreateUsersResponse { summary, created, errors }))
+        }
+    }
+}
@@ -3,7 +3,17 @@ pub mod error;
 pub mod handlers;
 pub mod models;
 pub mod repository;
+pub mod schema;
+pub mod types;
+pub mod build_info;
 pub mod auth_utils;
 pub mod monitoring;  // New monitoring module
 pub mod logging;     // New logging module
-pub mod middleware;  // New middleware module
\ No newline at end of file
+pub mod middleware;  // New middleware module
+pub mod rate_limit;
+pub mod tokens;
+pub mod normalize;
+pub mod cache;
+pub mod phone_verification;
+pub mod secrets;
+pub mod replica;
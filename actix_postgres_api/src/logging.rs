@@ -1,9 +1,16 @@
 use std::io;
-use tracing::{subscriber::set_global_default, Subscriber};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    subscriber::set_global_default,
+    Subscriber,
+};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::{
-    fmt::MakeWriter, prelude::*, registry::Registry, EnvFilter,
+    fmt::MakeWriter, layer::Context, prelude::*, registry::{LookupSpan, Registry}, EnvFilter, Layer,
 };
 
 /// Compose multiple layers into a `tracing`'s subscriber.
@@ -41,6 +48,7 @@ where
         .with(JsonStorageLayer)
         .with(formatting_layer)
         .with(bunyan_formatting_layer)
+        .with(SlowQueryLayer)
 }
 
 /// Register a subscriber as global default to process span data.
@@ -59,8 +67,6 @@ pub fn init_logging(app_name: &str, default_level: &str) {
     
     let subscriber = get_subscriber(app_name.into(), env_filter, io::stdout);
     init_subscriber(subscriber);
-    
-    tracing::info!("Initialized logging system with tracing");
 }
 
 /// Create a database query span which tracks performance
@@ -75,4 +81,85 @@ pub fn create_db_span(
         %sql,
         params = %params,
     )
-}
\ No newline at end of file
+}
+
+// Próg w mikrosekundach; u64::MAX oznacza wyłączone logowanie wolnych zapytań
+static SLOW_QUERY_THRESHOLD_MICROS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Sets how long a database query may take before it is logged as slow; `None` disables the log
+pub fn set_slow_query_threshold(threshold: Option<Duration>) {
+    let micros = threshold.map_or(u64::MAX, |threshold| threshold.as_micros().min(u64::MAX as u128 - 1) as u64);
+    SLOW_QUERY_THRESHOLD_MICROS.store(micros, Ordering::Relaxed);
+}
+
+fn slow_query_threshold() -> Option<Duration> {
+    match SLOW_QUERY_THRESHOLD_MICROS.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        micros => Some(Duration::from_micros(micros)),
+    }
+}
+
+// Czas i parametryzowany SQL zapytania; wartości parametrów celowo pomijamy, bo mogą zawierać dane osobowe
+struct QueryTiming {
+    started: Instant,
+    query_name: String,
+    sql: String,
+}
+
+#[derive(Default)]
+struct QueryFields {
+    query_name: String,
+    sql: String,
+}
+
+impl Visit for QueryFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "query_name" => self.query_name = format!("{:?}", value),
+            "sql" => self.sql = format!("{:?}", value),
+            _ => {}
+        }
+    }
+}
+
+/// Logs a warning for every `database_query` span that stays open longer than
+/// the slow-query threshold.
+///
+/// The warning is emitted after the query span has closed, so it carries the
+/// query name, SQL and duration but not the span's `params` field.
+pub struct SlowQueryLayer;
+
+impl<S> Layer<S> for SlowQueryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "database_query" || slow_query_threshold().is_none() {
+            return;
+        }
+        let mut fields = QueryFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(QueryTiming {
+                started: Instant::now(),
+                query_name: fields.query_name,
+                sql: fields.sql,
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(timing) = ctx.span(&id).and_then(|span| span.extensions_mut().remove::<QueryTiming>()) else {
+            return;
+        };
+        let elapsed = timing.started.elapsed();
+        if slow_query_threshold().is_some_and(|threshold| elapsed >= threshold) {
+            tracing::warn!(
+                query_name = %timing.query_name,
+                sql = %timing.sql,
+                duration_ms = elapsed.as_secs_f64() * 1000.0,
+                "Slow database query"
+            );
+        }
+    }
+}
@@ -1,15 +1,5 @@
-mod config;
-mod error;
-mod handlers;
-mod models;
-mod repository;
-mod auth_utils;
-mod monitoring;  // New monitoring module
-mod logging;     // New logging module
-mod middleware;  // New middleware module
-
 use actix_web::{middleware::Logger, web, App, HttpServer, HttpResponse};
-use actix_web_prom::{PrometheusMetricsBuilder, PrometheusMetrics};
+use actix_web_prom::PrometheusMetricsBuilder;
 use dotenv::dotenv;
 use sqlx::postgres::PgPoolOptions;
 use std::time::Duration;
@@ -17,36 +7,41 @@ use tokio::task;
 use tokio::time;
 use tracing_actix_web::TracingLogger;
 
-use crate::config::Config;
-use crate::handlers::{create_user, delete_user, get_all_users, get_user_by_id, update_user, login};
-use crate::repository::UserRepository;
-use crate::error::AppError;
-use crate::logging::init_logging;
-use crate::middleware::{CustomRootSpanBuilder, PerformanceMetrics};
-use crate::monitoring::update_memory_usage;
+use actix_postgres_api::auth_utils::init_hashing_pool;
+use actix_postgres_api::build_info::log_startup;
+use actix_postgres_api::cache::TrainerDirectoryCache;
+use actix_postgres_api::config::{Config, MaintenanceLevel, SmsSenderKind};
+use actix_postgres_api::handlers::{
+    create_user, delete_user, get_all_users, get_user_by_id, update_user, login,
+    bulk_create_users, search_users, get_password_policy, check_password, get_me, change_password, logout_all, health_check, get_version, get_maintenance_mode, set_maintenance_mode,
+    get_failed_request, get_user_duplicates, merge_users, reset_user_password, get_role_assignments, validate_field,
+    get_public_trainers, get_public_trainer, get_specialties, warm_trainer_directory, send_phone_code, confirm_phone,
+    validate_registration, search_users_ranked,
+};
+use actix_postgres_api::repository::{FailedRequestRepository, UserRepository};
+use actix_postgres_api::error::AppError;
+use actix_postgres_api::logging::{init_logging, set_slow_query_threshold};
+use actix_postgres_api::middleware::{CustomRootSpanBuilder, FailedRequestCapture, MaintenanceMode, MaintenanceState, PerformanceMetrics, RoleRateLimit, ServerTiming, TrailingSlash};
+use actix_postgres_api::monitoring::update_memory_usage;
+use actix_postgres_api::phone_verification::{sms_sender_from_config, SmsSender};
+use actix_postgres_api::rate_limit::{RequestRateLimiter, WriteRateLimiter};
+use actix_postgres_api::replica::{ReadPool, ReadReplica};
 
 // Handler do filtrowania użytkowników wg roli
 async fn get_users_by_role(
     role: web::Path<String>,
     db_pool: web::Data<sqlx::PgPool>,
+    read_pool: ReadPool,
 ) -> Result<HttpResponse, AppError> {
-    let repo = UserRepository::new(db_pool.get_ref().clone());
+    let repo = UserRepository::new(db_pool.get_ref().clone()).with_replica(read_pool.into_inner());
     let users = repo.find_by_role(&role).await?;
     
-    let response: Vec<crate::models::UserResponse> = 
-        users.into_iter().map(crate::models::UserResponse::from).collect();
+    let response: Vec<actix_postgres_api::models::UserResponse> = 
+        users.into_iter().map(actix_postgres_api::models::UserResponse::from).collect();
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-// Endpoint to expose application health status
-async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "up",
-        "version": env!("CARGO_PKG_VERSION")
-    }))
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Initialize environment variables
@@ -55,13 +50,17 @@ async fn main() -> std::io::Result<()> {
     // Initialize structured logging
     init_logging("actix_postgres_api", "info");
     
-    // Log startup information
-    tracing::info!("Starting up application");
-    tracing::info!("Version: {}", env!("CARGO_PKG_VERSION"));
-    
     // Read configuration
     let config = Config::from_env().expect("Failed to load configuration");
-    tracing::info!("Configuration loaded: {:?}", config);
+    
+    // Log build metadata and the effective configuration (secrets redacted)
+    log_startup(&config);
+    
+    // Zapytania dłuższe niż próg są logowane na poziomie WARN
+    set_slow_query_threshold(Some(Duration::from_millis(config.slow_query_threshold_ms)).filter(|t| !t.is_zero()));
+    
+    // Pula wątków dla bcrypt, aby hashowanie nie blokowało obsługi żądań
+    init_hashing_pool(config.hashing_threads);
     
     // Create database connection pool
     let pool = PgPoolOptions::new()
@@ -72,6 +71,12 @@ async fn main() -> std::io::Result<()> {
     
     tracing::info!("Database connection pool created successfully");
     
+    // Replika łączy się leniwie; dopóki jest niedostępna, odczyty idą do primary
+    let read_replica = web::Data::new(ReadReplica::from_config(&config).expect("Invalid READ_REPLICA_URL"));
+    if read_replica.pool().is_some() {
+        tracing::info!("GET requests will read from the replica");
+    }
+    
     // Setup Prometheus metrics
     let prometheus = PrometheusMetricsBuilder::new("api")
         .endpoint("/metrics")
@@ -89,12 +94,114 @@ async fn main() -> std::io::Result<()> {
         }
     });
     
+    // Zapisane treści nieudanych żądań usuwamy po upływie okresu retencji
+    if config.capture_failed_requests {
+        let failed_requests = FailedRequestRepository::new(pool.clone());
+        let retention_days = config.failed_request_retention_days;
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                match failed_requests.purge_older_than(retention_days).await {
+                    Ok(0) => {}
+                    Ok(purged) => tracing::info!("Purged {} captured failed requests", purged),
+                    Err(e) => tracing::warn!("Failed to purge captured failed requests: {}", e),
+                }
+            }
+        });
+    }
+    
+    // Konta usunięte dawniej niż okres retencji są usuwane na stałe
+    if config.deleted_user_retention_days > 0 {
+        let purge_repo = UserRepository::new(pool.clone());
+        let retention_days = config.deleted_user_retention_days;
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                match purge_repo.purge_deleted(retention_days).await {
+                    Ok(0) => {}
+                    Ok(purged) => tracing::info!("Purged {} users deleted more than {} days ago", purged, retention_days),
+                    Err(e) => tracing::warn!("Failed to purge deleted users: {}", e),
+                }
+            }
+        });
+    }
+    
+    // Cache katalogu trenerów jest wspólny dla wszystkich workerów
+    let trainer_cache = web::Data::new(TrainerDirectoryCache::new(Duration::from_secs(config.trainer_cache_ttl_secs)));
+    
+    // Pierwsze strony katalogu ładujemy przed startem, żeby zimny start nie podbijał opóźnień
+    if let Err(e) = warm_trainer_directory(&UserRepository::new(pool.clone()), &trainer_cache, config.trainer_cache_warm_pages).await {
+        tracing::warn!("Failed to warm up the trainer directory cache: {}", e);
+    }
+    
+    // Role czasowe po terminie wracają do roli domyślnej
+    let role_repo = UserRepository::new(pool.clone());
+    let default_role = config.default_role.clone();
+    let role_cache = trainer_cache.clone();
+    task::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match role_repo.expire_roles(&default_role).await {
+                Ok(0) => {}
+                Ok(expired) => {
+                    role_cache.invalidate();
+                    tracing::info!("Reverted {} expired roles to '{}'", expired, default_role);
+                }
+                Err(e) => tracing::warn!("Failed to expire temporary roles: {}", e),
+            }
+        }
+    });
+    
+    // Maintenance flag is shared by all workers so the admin toggle applies everywhere
+    let maintenance = web::Data::new(
+        MaintenanceState::new(config.maintenance_mode, config.maintenance_retry_after_secs)
+            .with_message(config.maintenance_message.clone()),
+    );
+    if config.maintenance_mode != MaintenanceLevel::Off {
+        tracing::warn!("Starting in maintenance mode ({:?})", config.maintenance_mode);
+    }
+    
+    // Limit zapisów profilu musi być wspólny dla wszystkich workerów
+    let write_limiter = web::Data::new(WriteRateLimiter::new(
+        config.profile_write_limit,
+        Duration::from_secs(config.profile_write_window_secs),
+    ));
+    
+    // Limity żądań według roli, również wspólne dla workerów
+    let request_limiter = web::Data::new(RequestRateLimiter::new(config.rate_limit_policy.clone()));
+    
+    // Bez bramki SMS (SMS_SENDER=disabled) wysyłanie kodów odpowiada 503
+    if config.sms_sender == SmsSenderKind::Log {
+        tracing::warn!("SMS_SENDER=log: verification codes are not delivered, use only in development");
+    }
+    let sms_sender: web::Data<dyn SmsSender> = web::Data::from(sms_sender_from_config(&config));
+    
+    let app_config = web::Data::new(config.clone());
+    
     tracing::info!("Starting server at http://{}:{}", config.host, config.port);
     
     // Start HTTP server
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(app_config.clone())
+            .app_data(maintenance.clone())
+            .app_data(write_limiter.clone())
+            .app_data(request_limiter.clone())
+            .app_data(trainer_cache.clone())
+            .app_data(sms_sender.clone())
+            .app_data(read_replica.clone())
+            // Store redacted bodies of failed writes when enabled
+            .wrap(FailedRequestCapture)
+            // Per-role request limits for /api routes
+            .wrap(RoleRateLimit)
+            // Reject non-health traffic while in maintenance mode
+            .wrap(MaintenanceMode)
+            // Report per-request timing breakdown when enabled
+            .wrap(ServerTiming)
             // Add Prometheus metrics
             .wrap(prometheus.clone())
             // Add tracing logger instead of standard logger
@@ -103,15 +210,28 @@ async fn main() -> std::io::Result<()> {
             .wrap(PerformanceMetrics)
             // Add standard logger as a fallback
             .wrap(Logger::default())
+            // Serve `/path/` like `/path` (trim or 308 redirect) before anything else sees the request
+            .wrap(TrailingSlash)
             // Health check endpoint
             .route("/health", web::get().to(health_check))
+            .route("/version", web::get().to(get_version))
             // API routes
             .service(
                 web::scope("/api")
+                    .route("/validate/field", web::post().to(validate_field))
+                    .service(
+                        web::scope("/public")
+                            .route("/trainers", web::get().to(get_public_trainers))
+                            .route("/trainers/{username}", web::get().to(get_public_trainer))
+                            .route("/specialties", web::get().to(get_specialties))
+                    )
                     .service(
                         web::scope("/users")
                             .route("", web::get().to(get_all_users))
                             .route("", web::post().to(create_user))
+                            .route("/bulk", web::post().to(bulk_create_users))
+                            .route("/search", web::get().to(search_users))
+                            .route("/search/ranked", web::get().to(search_users_ranked))
                             .route("/role/{role}", web::get().to(get_users_by_role))
                             .route("/{id}", web::get().to(get_user_by_id))
                             .route("/{id}", web::put().to(update_user))
@@ -120,6 +240,28 @@ async fn main() -> std::io::Result<()> {
                     .service(
                         web::scope("/auth")
                             .route("/login", web::post().to(login))
+                            .route("/register/validate", web::post().to(validate_registration))
+                            .route("/password-policy", web::get().to(get_password_policy))
+                            .route("/password-check", web::post().to(check_password))
+                    )
+                    .service(
+                        web::scope("/me")
+                            .route("", web::get().to(get_me))
+                            .route("/password", web::put().to(change_password))
+                            .route("/logout-all", web::post().to(logout_all))
+                            .route("/phone/verification", web::post().to(send_phone_code))
+                            .route("/phone/verify", web::post().to(confirm_phone))
+                    )
+                    .service(
+                        web::scope("/admin")
+                            .route("/maintenance", web::get().to(get_maintenance_mode))
+                            .route("/maintenance", web::put().to(set_maintenance_mode))
+                            .route("/maintenance", web::post().to(set_maintenance_mode))
+                            .route("/failed-requests/{request_id}", web::get().to(get_failed_request))
+                            .route("/users/duplicates", web::get().to(get_user_duplicates))
+                            .route("/role-assignments", web::get().to(get_role_assignments))
+                            .route("/users/{keep_id}/merge/{merge_id}", web::post().to(merge_users))
+                            .route("/users/{id}/reset-password", web::post().to(reset_user_password))
                     )
             )
     })
@@ -1,17 +1,32 @@
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderName, HeaderValue, CONTENT_LENGTH, LOCATION, RETRY_AFTER},
+        uri::PathAndQuery,
+        Method, Uri,
+    },
+    web::{self, BytesMut}, Error, HttpMessage, HttpRequest, HttpResponse, ResponseError,
 };
+use actix_http::BoxedPayloadStream;
 use futures::future::{ready, Ready, LocalBoxFuture};
-use std::{
-    rc::Rc,
-    time::Instant,
-};
+use futures::{stream, StreamExt};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::{cell::Cell, rc::Rc, sync::RwLock};
 use tracing::{event, Level, Span};
 use tracing_actix_web::{RootSpanBuilder};
 use uuid::Uuid;
 
-use crate::monitoring::{HTTP_REQUEST_COUNTER, HTTP_REQUEST_DURATION, Timer, ACTIVE_CONNECTIONS};
+use crate::config::{Config, MaintenanceLevel, TrailingSlashPolicy};
+use crate::error::{AppError, FieldError};
+use crate::handlers::require_admin_key;
+use crate::models::FailedRequest;
+use crate::repository::{FailedRequestRepository, UserRepository};
+use crate::rate_limit::{client_address, CallerTier, RequestRateLimiter};
+use crate::tokens::{bearer_token, decode_access_token, verify_access_token};
+use crate::monitoring::{HTTP_REQUEST_COUNTER, HTTP_REQUEST_DURATION, RequestTimings, Timer, ACTIVE_CONNECTIONS};
 
 // Custom root span builder for tracing-actix-web
 pub struct CustomRootSpanBuilder;
@@ -126,4 +141,583 @@ where
             Ok(res)
         })
     }
-}
\ No newline at end of file
+}
+
+// Paths that stay reachable while maintenance mode is on
+const MAINTENANCE_EXEMPT_PATHS: &[&str] = &["/health", "/api/admin/maintenance"];
+
+// Zapisy dozwolone w trybie read_only: logowanie zapisuje tylko liczniki prób,
+// a bez niego użytkownicy nie mogliby nawet czytać swoich danych
+const READ_ONLY_ALLOWED_WRITES: &[(Method, &str)] = &[(Method::POST, "/api/auth/login")];
+
+const DEFAULT_MAINTENANCE_MESSAGE: &str =
+    "The service is temporarily down for maintenance. Please try again later.";
+
+/// Current maintenance settings, as reported by `/health` and the admin endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MaintenanceStatus {
+    pub mode: MaintenanceLevel,
+    pub message: Option<String>,  // Komunikat operatora zwracany w odpowiedziach 503
+    pub ends_at: Option<DateTime<Utc>>,  // Planowany koniec prac, steruje Retry-After
+}
+
+// Shared maintenance settings, changed at runtime through the admin endpoint
+pub struct MaintenanceState {
+    status: RwLock<MaintenanceStatus>,
+    retry_after_secs: u64,
+}
+
+impl MaintenanceState {
+    pub fn new(mode: MaintenanceLevel, retry_after_secs: u64) -> Self {
+        Self {
+            status: RwLock::new(MaintenanceStatus {
+                mode,
+                message: None,
+                ends_at: None,
+            }),
+            retry_after_secs,
+        }
+    }
+
+    /// Sets the operator message shown in 503 responses
+    pub fn with_message(mut self, message: Option<String>) -> Self {
+        self.status.get_mut().unwrap_or_else(|e| e.into_inner()).message = message;
+        self
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        self.status.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.status().mode != MaintenanceLevel::Off
+    }
+
+    /// Replaces the settings, returning the previous ones
+    pub fn set(&self, status: MaintenanceStatus) -> MaintenanceStatus {
+        let mut current = self.status.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *current, status)
+    }
+
+    // Do planowanego końca prac, a bez niego - wartość domyślna z konfiguracji
+    fn retry_after_secs(&self, status: &MaintenanceStatus) -> u64 {
+        match status.ends_at {
+            Some(ends_at) => (ends_at - Utc::now()).num_seconds().max(1) as u64,
+            None => self.retry_after_secs,
+        }
+    }
+}
+
+// Czy żądanie zostaje zablokowane przy danym poziomie trybu serwisowego
+fn blocked_by_maintenance(mode: MaintenanceLevel, method: &Method, path: &str) -> bool {
+    if MAINTENANCE_EXEMPT_PATHS.contains(&path) {
+        return false;
+    }
+    match mode {
+        MaintenanceLevel::Off => false,
+        MaintenanceLevel::ReadOnly => {
+            !matches!(*method, Method::GET | Method::HEAD)
+                && !READ_ONLY_ALLOWED_WRITES
+                    .iter()
+                    .any(|(allowed, allowed_path)| allowed == method && *allowed_path == path)
+        }
+        MaintenanceLevel::Full => true,
+    }
+}
+
+// Maintenance mode middleware - answers 503 for requests the current level blocks.
+// The level is checked when a request arrives, so requests already in flight
+// when maintenance is switched on run to completion.
+pub struct MaintenanceMode;
+
+impl<S, B> Transform<S, ServiceRequest> for MaintenanceMode
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = MaintenanceModeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MaintenanceModeMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct MaintenanceModeMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for MaintenanceModeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Bez zarejestrowanego stanu middleware niczego nie blokuje
+        let blocked = req
+            .app_data::<web::Data<MaintenanceState>>()
+            .map(|state| (state.status(), state))
+            .filter(|(status, _)| blocked_by_maintenance(status.mode, req.method(), req.path()))
+            .map(|(status, state)| (state.retry_after_secs(&status), status));
+
+        match blocked {
+            Some((retry_after, status)) => {
+                let response = HttpResponse::ServiceUnavailable()
+                    .insert_header((RETRY_AFTER, retry_after.to_string()))
+                    .json(serde_json::json!({
+                        "status": "503 Service Unavailable",
+                        "message": status.message.as_deref().unwrap_or(DEFAULT_MAINTENANCE_MESSAGE),
+                        "mode": status.mode,
+                        "ends_at": status.ends_at,
+                        "retry_after": retry_after,
+                    }));
+
+                Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) })
+            }
+            None => {
+                let service = self.service.clone();
+                Box::pin(async move {
+                    let res = service.call(req).await?;
+                    Ok(res.map_into_left_body())
+                })
+            }
+        }
+    }
+}
+
+// Per-role request limit - callers are identified by the admin key, a fully verified
+// access token (subject and current role), or the client address for anonymous requests.
+// Only /api routes are limited, so health checks and metrics scrapes never are
+pub struct RoleRateLimit;
+
+impl<S, B> Transform<S, ServiceRequest> for RoleRateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RoleRateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RoleRateLimitMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RoleRateLimitMiddleware<S> {
+    service: Rc<S>,
+}
+
+// Token bez poprawnego podpisu albo unieważniony (logout-all, usunięte lub nieaktywne
+// konto) nie identyfikuje wywołującego - liczy się jak żądanie anonimowe. Rola pochodzi
+// z bazy, więc zmiana roli od razu zmienia limit
+async fn rate_limit_caller(req: &HttpRequest, config: &Config) -> (CallerTier, String) {
+    if require_admin_key(req, config).is_ok() {
+        return (CallerTier::Admin, "admin".to_string());
+    }
+    let anonymous = || (CallerTier::Anonymous, client_address(req));
+    let Some(token) = bearer_token(req) else {
+        return anonymous();
+    };
+    let Ok(claims) = decode_access_token(token, &config.jwt_secret) else {
+        return anonymous();
+    };
+    let from_claims = || (CallerTier::from_role(claims.role.as_deref()), claims.sub.to_string());
+    let Some(pool) = req.app_data::<web::Data<PgPool>>() else {
+        return from_claims();
+    };
+
+    match verify_access_token(token, &config.jwt_secret, &UserRepository::new(pool.get_ref().clone())).await {
+        Ok(user) => (CallerTier::from_role(Some(&user.role)), user.id.to_string()),
+        Err(AppError::Unauthorized(_)) => anonymous(),
+        // Przy niedostępnej bazie zostaje rola z tokenu, żeby awaria nie wrzuciła wszystkich do jednego limitu
+        Err(_) => from_claims(),
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RoleRateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Bez zarejestrowanego limitera middleware niczego nie blokuje
+        let limiter = req.app_data::<web::Data<RequestRateLimiter>>().cloned();
+        let config = req.app_data::<web::Data<Config>>().cloned();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let rejected = match limiter.zip(config) {
+                Some((limiter, config)) if req.path().starts_with("/api") => {
+                    let (tier, caller) = rate_limit_caller(req.request(), &config).await;
+                    limiter.check(tier, &caller).err().map(|retry_after| (tier, retry_after))
+                }
+                _ => None,
+            };
+
+            if let Some((tier, retry_after)) = rejected {
+                tracing::debug!(tier = tier.as_str(), "Request rate limit exceeded");
+                let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+                let response = AppError::RateLimitExceeded(retry_after).error_response();
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+// Server-Timing middleware - reports db, hash, validation and total time per request
+// when `server_timing` is enabled in the config
+pub struct ServerTiming;
+
+impl<S, B> Transform<S, ServiceRequest> for ServerTiming
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ServerTimingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ServerTimingMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ServerTimingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ServerTimingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let enabled = req
+            .app_data::<web::Data<Config>>()
+            .map(|config| config.server_timing)
+            .unwrap_or(false);
+
+        if !enabled {
+            return Box::pin(self.service.call(req));
+        }
+
+        let timer = Timer::new();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let (res, timings) = RequestTimings::scope(service.call(req)).await;
+            let mut res = res?;
+
+            let header = format!(
+                "db;dur={:.3}, hash;dur={:.3}, validation;dur={:.3}, total;dur={:.3}",
+                timings.db_seconds * 1000.0,
+                timings.hash_seconds * 1000.0,
+                timings.validation_seconds * 1000.0,
+                timer.elapsed_seconds() * 1000.0,
+            );
+            if let Ok(value) = HeaderValue::from_str(&header) {
+                res.headers_mut().insert(HeaderName::from_static("server-timing"), value);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+// Trailing slash middleware - `/api/users/` and `/api/users` reach the same handler,
+// either by trimming the slash before routing or by a 308 redirect (`trailing_slash`)
+pub struct TrailingSlash;
+
+impl<S, B> Transform<S, ServiceRequest> for TrailingSlash
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = TrailingSlashMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TrailingSlashMiddleware { service }))
+    }
+}
+
+pub struct TrailingSlashMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TrailingSlashMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let path = req.path();
+        // Ścieżka główna "/" zostaje bez zmian
+        if path.len() <= 1 || !path.ends_with('/') {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(ServiceResponse::map_into_boxed_body) });
+        }
+
+        let trimmed = match path.trim_end_matches('/') {
+            "" => "/",
+            trimmed => trimmed,
+        };
+        let target = match req.query_string() {
+            "" => trimmed.to_string(),
+            query => format!("{}?{}", trimmed, query),
+        };
+        let policy = req
+            .app_data::<web::Data<Config>>()
+            .map(|config| config.trailing_slash)
+            .unwrap_or(TrailingSlashPolicy::Merge);
+
+        if policy == TrailingSlashPolicy::Redirect {
+            let response = HttpResponse::PermanentRedirect()
+                .insert_header((LOCATION, target))
+                .finish();
+            return Box::pin(async move { Ok(req.into_response(response)) });
+        }
+
+        // Podmieniamy URI przed routingiem, tak jak actix_web::middleware::NormalizePath
+        let mut parts = req.head().uri.clone().into_parts();
+        parts.path_and_query = target.parse::<PathAndQuery>().ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            req.match_info_mut().get_mut().update(&uri);
+            req.head_mut().uri = uri;
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_boxed_body) })
+    }
+}
+
+// Ścieżki, których treści nie zapisujemy nigdy, niezależnie od konfiguracji
+const CAPTURE_EXCLUDED_PREFIXES: &[&str] = &["/api/auth/"];
+
+// Klucze, których wartości są maskowane przed zapisem (dopasowanie fragmentu nazwy)
+const REDACTED_KEY_FRAGMENTS: &[&str] = &["password", "token", "secret"];
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Replaces the values of password, token and secret fields anywhere in the document
+pub fn redact_sensitive_fields(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if REDACTED_KEY_FRAGMENTS.iter().any(|fragment| key.contains(fragment)) {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_sensitive_fields(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_sensitive_fields),
+        _ => {}
+    }
+}
+
+fn insert_request_id<B>(res: &mut ServiceResponse<B>, request_id: Uuid) {
+    if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+        res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+}
+
+// Treść do zapisu: zamaskowana i ograniczona rozmiarem; zwraca (body, truncated)
+fn captured_body(bytes: &[u8], max_bytes: usize) -> (Option<serde_json::Value>, bool) {
+    if bytes.len() > max_bytes {
+        return (None, true);
+    }
+    // Treści niebędących JSON-em nie da się bezpiecznie zamaskować, więc ich nie zapisujemy
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(mut body) => {
+            redact_sensitive_fields(&mut body);
+            (Some(body), false)
+        }
+        Err(_) => (None, false),
+    }
+}
+
+// Failed request capture middleware - with `capture_failed_requests` enabled, stores
+// the redacted body of mutating requests that end with 4xx/5xx. Every response gets
+// an X-Request-Id header so clients can quote it when reporting a failure.
+pub struct FailedRequestCapture;
+
+impl<S, B> Transform<S, ServiceRequest> for FailedRequestCapture
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = FailedRequestCaptureMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(FailedRequestCaptureMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct FailedRequestCaptureMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for FailedRequestCaptureMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        // Identyfikator nadany przez CustomRootSpanBuilder, jeśli TracingLogger działa
+        let request_id = req.extensions().get::<Uuid>().copied().unwrap_or_else(Uuid::new_v4);
+
+        let is_mutating = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        let excluded = CAPTURE_EXCLUDED_PREFIXES.iter().any(|prefix| req.path().starts_with(prefix));
+        let capture = req
+            .app_data::<web::Data<Config>>()
+            .filter(|config| config.capture_failed_requests && is_mutating && !excluded)
+            .map(|config| config.failed_request_max_body_bytes)
+            .zip(req.app_data::<web::Data<PgPool>>().cloned());
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            // Buforujemy najwyżej tyle, ile da się zapisać (plus jeden fragment); resztę
+            // handler czyta prosto z połączenia, a my tylko liczymy jej bajty
+            let captured = match capture {
+                Some((max_bytes, pool)) => {
+                    let mut payload = req.take_payload();
+                    let mut bytes = BytesMut::new();
+                    while bytes.len() <= max_bytes {
+                        match payload.next().await {
+                            Some(Ok(chunk)) => bytes.extend_from_slice(&chunk),
+                            Some(Err(e)) => {
+                                let error = AppError::MalformedBody(FieldError::new("", format!("Could not read request body: {}", e)));
+                                let mut res = req.into_response(error.error_response()).map_into_right_body();
+                                insert_request_id(&mut res, request_id);
+                                return Ok(res);
+                            }
+                            None => break,
+                        }
+                    }
+                    let bytes = bytes.freeze();
+
+                    let body_size = Rc::new(Cell::new(bytes.len()));
+                    let counter = body_size.clone();
+                    let rest = payload.inspect(move |chunk| {
+                        if let Ok(chunk) = chunk {
+                            counter.set(counter.get() + chunk.len());
+                        }
+                    });
+                    let replay: BoxedPayloadStream = Box::pin(stream::once(ready(Ok(bytes.clone()))).chain(rest));
+                    req.set_payload(Payload::from(replay));
+
+                    Some((bytes, body_size, max_bytes, pool))
+                }
+                None => None,
+            };
+
+            let method = req.method().to_string();
+            let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+            let content_length = req
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<usize>().ok());
+
+            let mut res = service.call(req).await?.map_into_left_body();
+            insert_request_id(&mut res, request_id);
+
+            let status = res.status();
+            if let Some((bytes, body_size, max_bytes, pool)) = captured.filter(|_| status.is_client_error() || status.is_server_error()) {
+                let error_code = res
+                    .response()
+                    .error()
+                    .and_then(|error| error.as_error::<AppError>())
+                    .map(|error| error.code().to_string());
+                let (body, body_truncated) = captured_body(&bytes, max_bytes);
+
+                let failed = FailedRequest {
+                    request_id,
+                    method,
+                    route,
+                    status: status.as_u16() as i16,
+                    error_code,
+                    body,
+                    // Handler może odrzucić treść bez czytania jej do końca
+                    body_size: body_size.get().max(content_length.unwrap_or(0)).min(i32::MAX as usize) as i32,
+                    body_truncated,
+                    created_at: Utc::now(),
+                };
+                // Błąd zapisu nie może zmienić odpowiedzi dla klienta
+                if let Err(e) = FailedRequestRepository::new(pool.get_ref().clone()).record(&failed).await {
+                    tracing::warn!("Failed to capture failed request {}: {}", request_id, e);
+                }
+            }
+
+            Ok(res)
+        })
+    }
+}
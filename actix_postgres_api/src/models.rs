@@ -1,37 +1,44 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::types::Uuid;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::config::MaintenanceLevel;
+use crate::types::{deserialize_enum, Email, ParseEnum, PhoneNumber, Username};
 
 // Enum reprezentujący role użytkowników
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, Serialize, sqlx::Type, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "varchar", rename_all = "lowercase")]
 pub enum UserRole {
+    #[default]
     Client,
     Trainer,
 }
 
-impl Default for UserRole {
-    fn default() -> Self {
-        UserRole::Client
+impl ParseEnum for UserRole {
+    const FIELD: &'static str = "role";
+    const NAMES: &'static [&'static str] = &["client", "trainer"];
+    const VARIANTS: &'static [Self] = &[Self::Client, Self::Trainer];
+}
+
+impl<'de> Deserialize<'de> for UserRole {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_enum(deserializer)
     }
 }
 
 // Implementacja konwersji z i do stringa dla UserRole
-impl ToString for UserRole {
-    fn to_string(&self) -> String {
-        match self {
-            UserRole::Client => "client".to_string(),
-            UserRole::Trainer => "trainer".to_string(),
-        }
+impl fmt::Display for UserRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
 impl From<&str> for UserRole {
     fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "trainer" => UserRole::Trainer,
-            _ => UserRole::Client, // domyślnie ustawiamy Client
-        }
+        Self::from_name(s).unwrap_or_default() // domyślnie ustawiamy Client
     }
 }
 
@@ -47,16 +54,45 @@ pub struct User {
     pub role: String,  // Role as string to simplify database interaction
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,  // Set when the account is soft-deleted
+    pub token_version: i32,  // Embedded in access tokens; bumping it revokes them all
+    pub role_expires_at: Option<DateTime<Utc>>,  // When a temporary role reverts to the default role
+    pub public_profile: bool,  // Trainer opted in to the public profile pages
+    pub phone_verified_at: Option<DateTime<Utc>>,  // Cleared whenever the phone number changes
+    pub bio: Option<String>,  // Trainer only
+    pub hourly_rate: Option<i32>,  // Trainer only, whole currency units
+    pub fitness_goals: Option<String>,  // Client only
+    pub password_change_required: bool,  // Set by an admin reset, cleared by the next password change
+    pub failed_login_attempts: i32,  // Since the last successful login or lockout
+    pub locked_until: Option<DateTime<Utc>>,
+    pub lockout_count: i32,  // Lockouts inside the escalation window
+    pub last_lockout_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// Whether the stored phone number fails the current format rules (legacy data).
+    /// Such numbers are still returned on read, but must be replaced on the next update.
+    pub fn phone_needs_update(&self) -> bool {
+        self.phone_number
+            .as_deref()
+            .is_some_and(|phone| PhoneNumber::parse(phone).is_err())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUserRequest {
+    pub id: Option<String>,  // Optional client-generated UUID, makes retried creates idempotent
     pub username: String,
     pub email: String,
     pub password: String,  // Plain text password (only used for creation)
     pub full_name: String,
     pub phone_number: Option<String>,
     pub role: Option<String>,  // Optional role - if not provided, default to CLIENT
+    pub role_expires_at: Option<DateTime<Utc>>,  // Makes the role temporary
+    pub bio: Option<String>,
+    pub hourly_rate: Option<i32>,
+    pub fitness_goals: Option<String>,
+    pub specialties: Option<Vec<String>>,  // Slugs from the specialties table, trainers only
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,20 +101,257 @@ pub struct UpdateUserRequest {
     pub email: Option<String>,
     pub password: Option<String>,  // Optional password update
     pub full_name: Option<String>,
-    pub phone_number: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_nullable", skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<Option<String>>,  // Some(None), i.e. an explicit null, removes the number
     pub active: Option<bool>,
-    pub role: Option<String>,  // Optional role update
+    pub role: Option<UserRole>,  // Optional role update, rejected at deserialization if unknown
+    pub role_expires_at: Option<DateTime<Utc>>,  // Makes the (new or current) role temporary
+    pub public_profile: Option<bool>,
+    pub bio: Option<String>,
+    pub hourly_rate: Option<i32>,
+    pub fitness_goals: Option<String>,
+    pub specialties: Option<Vec<String>>,  // Replaces the trainer's whole list
+}
+
+// Odróżnia pominięte pole (None) od jawnego null (Some(None))
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl UpdateUserRequest {
+    /// Names of the fields the request actually sets
+    pub fn supplied_fields(&self) -> Vec<&'static str> {
+        [
+            ("username", self.username.is_some()),
+            ("email", self.email.is_some()),
+            ("password", self.password.is_some()),
+            ("full_name", self.full_name.is_some()),
+            ("phone_number", self.phone_number.is_some()),
+            ("active", self.active.is_some()),
+            ("role", self.role.is_some()),
+            ("role_expires_at", self.role_expires_at.is_some()),
+            ("public_profile", self.public_profile.is_some()),
+            ("bio", self.bio.is_some()),
+            ("hourly_rate", self.hourly_rate.is_some()),
+            ("fitness_goals", self.fitness_goals.is_some()),
+            ("specialties", self.specialties.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, supplied)| supplied.then_some(name))
+        .collect()
+    }
+}
+
+// Pola profilu, które może ustawić tylko jedna rola (reguły w auth_utils::ROLE_SPECIFIC_FIELDS);
+// None oznacza, że pola nie podano
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RoleSpecificFields {
+    pub bio: Option<String>,
+    pub hourly_rate: Option<i32>,
+    pub fitness_goals: Option<String>,
+    pub specialties: Option<Vec<String>>,  // Stored in trainer_specialties, not on the users row
+}
+
+impl RoleSpecificFields {
+    /// Names of the fields that were supplied
+    pub fn supplied(&self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.bio.is_some() {
+            fields.push("bio");
+        }
+        if self.hourly_rate.is_some() {
+            fields.push("hourly_rate");
+        }
+        if self.fitness_goals.is_some() {
+            fields.push("fitness_goals");
+        }
+        if self.specialties.is_some() {
+            fields.push("specialties");
+        }
+        fields
+    }
+
+    pub fn clear(&mut self, field: &str) {
+        match field {
+            "bio" => self.bio = None,
+            "hourly_rate" => self.hourly_rate = None,
+            "fitness_goals" => self.fitness_goals = None,
+            "specialties" => self.specialties = None,
+            _ => {}
+        }
+    }
+}
+
+// Tryb masowego tworzenia użytkowników
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+    #[default]
+    Atomic,  // Wszystko albo nic
+    PerRow,  // Poprawne wiersze są zapisywane, błędne raportowane
+}
+
+impl ParseEnum for BulkMode {
+    const FIELD: &'static str = "mode";
+    const NAMES: &'static [&'static str] = &["atomic", "per_row"];
+    const VARIANTS: &'static [Self] = &[Self::Atomic, Self::PerRow];
+}
+
+impl<'de> Deserialize<'de> for BulkMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_enum(deserializer)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkCreateUsersRequest {
+    pub users: Vec<serde_json::Value>,  // Each row is validated as a CreateUserRequest
+    pub mode: Option<BulkMode>,
+}
+
+// Zawartość odpowiedzi na masowe tworzenie (?return=...), jak w PostgREST
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnPreference {
+    #[default]
+    Minimal,  // Tylko indeks i id utworzonego wiersza
+    Representation,  // Cały utworzony użytkownik, bez pól wrażliwych
+}
+
+impl ParseEnum for ReturnPreference {
+    const FIELD: &'static str = "return";
+    const NAMES: &'static [&'static str] = &["minimal", "representation"];
+    const VARIANTS: &'static [Self] = &[Self::Minimal, Self::Representation];
+}
+
+impl<'de> Deserialize<'de> for ReturnPreference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_enum(deserializer)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkCreateQuery {
+    #[serde(rename = "return")]
+    pub return_preference: Option<ReturnPreference>,
+    pub max_errors: Option<usize>,  // Limits the per-row details; the summary always counts every row
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkCreatedRow {
+    pub index: usize,
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserResponse>,  // Only with return=representation
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkRowError {
+    pub index: usize,
+    pub field: String,
+    pub code: &'static str,  // Same code as the matching single-user error, e.g. "VALIDATION_ERROR"
+    pub message: String,
+}
+
+// Zbiorcze wyniki partii - liczone przed ewentualnym obcięciem listy błędów
+#[derive(Debug, Serialize)]
+pub struct BulkSummary {
+    pub total: usize,
+    pub valid: usize,
+    pub invalid: usize,
+    pub error_codes: BTreeMap<&'static str, usize>,
+    pub errors_truncated: bool,
+}
+
+impl BulkSummary {
+    pub fn new(total: usize, valid: usize, errors: &[BulkRowError]) -> Self {
+        let mut error_codes = BTreeMap::new();
+        for error in errors {
+            *error_codes.entry(error.code).or_insert(0) += 1;
+        }
+        let mut invalid_rows: Vec<usize> = errors.iter().map(|e| e.index).collect();
+        invalid_rows.sort_unstable();
+        invalid_rows.dedup();
+        Self {
+            total,
+            valid,
+            invalid: invalid_rows.len(),
+            error_codes,
+            errors_truncated: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkCreateUsersResponse {
+    pub summary: BulkSummary,
+    pub created: Vec<BulkCreatedRow>,
+    pub errors: Vec<BulkRowError>,
+}
+
+// Zwalidowane dane nowego użytkownika przekazywane do repozytorium
+#[derive(Debug)]
+pub struct NewUser {
+    pub id: Option<Uuid>,  // Client-supplied id; generated by the database when None
+    pub username: Username,
+    pub email: Email,
+    pub password: String,  // Plain text password, hashed by the repository
+    pub full_name: String,
+    pub phone_number: Option<PhoneNumber>,
+    pub role: Option<String>,  // Already validated role - defaults to CLIENT
+    pub role_expires_at: Option<DateTime<Utc>>,
+    pub role_fields: RoleSpecificFields,  // Already checked against the role
+}
+
+// Zwalidowane zmiany użytkownika; None oznacza brak zmiany pola
+#[derive(Debug, Default)]
+pub struct UserChanges {
+    pub username: Option<Username>,
+    pub email: Option<Email>,
+    pub password: Option<String>,
+    pub full_name: Option<String>,
+    pub phone_number: Option<Option<PhoneNumber>>,  // Some(None) removes the number
+    pub active: Option<bool>,
+    pub role: Option<String>,
+    pub role_expires_at: Option<Option<DateTime<Utc>>>,  // Some(None) makes the role permanent
+    pub public_profile: Option<bool>,
+    pub role_fields: RoleSpecificFields,
+}
+
+impl UserChanges {
+    /// Whether profile data is edited, as opposed to only account state
+    /// (`active`, password, role) or the phone number itself
+    pub fn edits_profile(&self) -> bool {
+        self.username.is_some()
+            || self.email.is_some()
+            || self.full_name.is_some()
+            || self.public_profile.is_some()
+            || !self.role_fields.supplied().is_empty()
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: Uuid,
     pub username: String,
-    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,  // Left out where the caller may not see email addresses
     pub full_name: String,
     pub phone_number: Option<String>,
     pub active: bool,
     pub role: String,  // Included in response
+    pub role_expires_at: Option<DateTime<Utc>>,
+    pub public_profile: bool,
+    pub phone_verified: bool,
+    pub bio: Option<String>,
+    pub hourly_rate: Option<i32>,
+    pub fitness_goals: Option<String>,
+    pub password_change_required: bool,
+    pub phone_needs_update: bool,  // Stored phone predates the current format rules
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     // Nie zwracamy password_hash w odpowiedzi API
@@ -86,14 +359,23 @@ pub struct UserResponse {
 
 impl From<User> for UserResponse {
     fn from(user: User) -> Self {
+        let phone_needs_update = user.phone_needs_update();
         Self {
             id: user.id,
             username: user.username,
-            email: user.email,
+            email: Some(user.email),
             full_name: user.full_name,
             phone_number: user.phone_number,
             active: user.active,
             role: user.role,
+            role_expires_at: user.role_expires_at,
+            public_profile: user.public_profile,
+            phone_verified: user.phone_verified_at.is_some(),
+            bio: user.bio,
+            hourly_rate: user.hourly_rate,
+            fitness_goals: user.fitness_goals,
+            password_change_required: user.password_change_required,
+            phone_needs_update,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
@@ -111,4 +393,248 @@ pub struct LoginRequest {
 pub struct LoginResponse {
     pub user: UserResponse,
     pub message: String,
-}
\ No newline at end of file
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,  // Access token lifetime in seconds
+}
+
+// Sprawdzenie kandydata na hasło (POST /auth/password-check), bez zapisu
+#[derive(Debug, Deserialize)]
+pub struct PasswordCheckRequest {
+    pub password: String,
+}
+
+// Jedna pozycja listy wymagań hasła
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PasswordRequirementResult {
+    pub requirement: &'static str,  // e.g. "min_length", "digit", "not_common"
+    pub passed: bool,
+    pub enforced: bool,  // false for advisory checks that registration does not reject
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasswordCheckResponse {
+    pub acceptable: bool,  // Whether registration would accept the password
+    pub requirements: Vec<PasswordRequirementResult>,
+}
+
+// Wynik resetu hasła przez administratora; hasło tymczasowe jest zwracane tylko raz
+#[derive(Debug, Serialize)]
+pub struct PasswordResetResponse {
+    pub user: UserResponse,
+    pub temporary_password: String,
+}
+
+// Przechwycone nieudane żądanie modyfikujące (tryb CAPTURE_FAILED_REQUESTS)
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct FailedRequest {
+    pub request_id: Uuid,
+    pub method: String,
+    pub route: String,
+    pub status: i16,
+    pub error_code: Option<String>,
+    pub body: Option<serde_json::Value>,  // Redacted; None when oversized or not JSON
+    pub body_size: i32,
+    pub body_truncated: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+// Oczekujący kod weryfikacji telefonu; sam kod przechowywany jest wyłącznie jako hash
+#[derive(Debug, sqlx::FromRow)]
+pub struct PhoneVerification {
+    pub user_id: Uuid,
+    pub phone_number: String,  // Number the code was sent to
+    pub code_hash: String,
+    pub attempts: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyPhoneRequest {
+    pub code: String,
+}
+
+// Zmiana własnego hasła (PUT /me/password), także hasła tymczasowego po resecie
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+// Parametry wyszukiwania użytkowników (GET /users/search?q=...)
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+}
+
+// Wynik wyszukiwania łączonego: użytkownik, najlepiej dopasowane pole i wynik trafności
+#[derive(Debug, sqlx::FromRow)]
+pub struct RankedUser {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub matched_on: String,
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RankedUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub matched_on: String,  // "username", "full_name" or "email"
+    pub score: f32,  // Between 0 and 1, higher is a closer match
+}
+
+impl RankedUserResponse {
+    /// Builds a search result; the email is only included when the caller may see it
+    pub fn new(ranked: RankedUser, include_email: bool) -> Self {
+        let mut user = UserResponse::from(ranked.user);
+        if !include_email {
+            user.email = None;
+        }
+        Self {
+            user,
+            matched_on: ranked.matched_on,
+            score: ranked.score,
+        }
+    }
+}
+
+// Walidacja pojedynczego pola formularza (POST /validate/field)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateFieldRequest {
+    pub field: String,
+    pub value: String,
+    pub context: Option<serde_json::Value>,  // Other form values; accepted for future cross-field rules
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidateFieldResponse {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+}
+
+// Etap kreatora rejestracji walidowany bez zapisu; wszystkie pola są opcjonalne
+#[derive(Debug, Default, Deserialize)]
+pub struct RegistrationDraft {
+    pub id: Option<String>,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub full_name: Option<String>,
+    pub phone_number: Option<String>,
+    pub role: Option<String>,
+    pub role_expires_at: Option<DateTime<Utc>>,
+    pub bio: Option<String>,
+    pub hourly_rate: Option<i32>,
+    pub fitness_goals: Option<String>,
+    pub specialties: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegistrationValidationResponse {
+    pub ok: bool,
+    pub errors: HashMap<String, Vec<String>>,  // Field name -> error messages, empty when ok
+}
+
+// Stronicowanie list (?page=...&per_page=...)
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+// Katalog trenerów (?page=...&per_page=...&specialty=...)
+#[derive(Debug, Deserialize)]
+pub struct TrainerDirectoryQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    pub specialty: Option<String>,  // Slug; only trainers with this specialty are listed
+}
+
+// Pozycja słownika specjalizacji
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Specialty {
+    pub slug: String,
+    pub name: String,
+}
+
+// Trener z katalogu razem z jego specjalizacjami
+#[derive(Debug, sqlx::FromRow)]
+pub struct PublicTrainer {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub specialties: Vec<String>,
+}
+
+// Publiczny profil trenera - celowo osobny typ od UserResponse, żeby nowe pola
+// konta nie trafiały same do publicznych odpowiedzi
+#[derive(Debug, Serialize)]
+pub struct PublicTrainerProfile {
+    pub username: String,
+    pub display_name: String,
+    pub specialties: Vec<String>,
+}
+
+impl From<PublicTrainer> for PublicTrainerProfile {
+    fn from(trainer: PublicTrainer) -> Self {
+        Self {
+            username: trainer.user.username,
+            display_name: trainer.user.full_name,
+            specialties: trainer.specialties,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicTrainerList {
+    pub trainers: Vec<PublicTrainerProfile>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: i64,
+}
+
+// Para kont, które mogą należeć do tej samej osoby
+#[derive(Debug, sqlx::FromRow)]
+pub struct DuplicatePair {
+    pub first_id: Uuid,
+    pub second_id: Uuid,
+    pub phone_match: bool,
+    pub email_similarity: f32,  // Trigram similarity of email local parts
+    pub name_similarity: f32,   // Trigram similarity of full names
+}
+
+// Grupa kont uznanych za duplikaty (spójna składowa par)
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub confidence: f32,
+    pub reasons: Vec<&'static str>,  // Signals that linked the accounts: phone_number, email, full_name
+    pub users: Vec<UserResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: usize,
+}
+
+// Filtr listy czasowych ról (GET /admin/role-assignments?expiring_before=...)
+#[derive(Debug, Deserialize)]
+pub struct RoleAssignmentsQuery {
+    pub expiring_before: Option<DateTime<Utc>>,
+}
+
+// Przełączanie trybu serwisowego; `mode` ma pierwszeństwo przed `enabled`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: Option<bool>,  // true = full, kept for the original on/off toggle
+    pub mode: Option<MaintenanceLevel>,
+    pub message: Option<String>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
@@ -2,7 +2,7 @@ use prometheus::{
     register_histogram_vec, register_int_counter_vec, register_int_gauge,
     HistogramVec, IntCounterVec, IntGauge,
 };
-use std::time::Instant;
+use std::{cell::RefCell, future::Future, time::Instant};
 
 // Lazy static to hold our metrics
 lazy_static::lazy_static! {
@@ -41,6 +41,24 @@ lazy_static::lazy_static! {
         "api_memory_usage_bytes",
         "Current memory usage in bytes"
     ).unwrap();
+
+    pub static ref DB_READ_ROUTING_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "api_db_read_routing_total",
+        "Reads routed to the read replica, or to the primary after the replica was unavailable",
+        &["pool"]
+    ).unwrap();
+
+    pub static ref LOGIN_FAILURE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "api_login_failures_total",
+        "Rejected logins by internal reason",
+        &["reason"]
+    ).unwrap();
+
+    pub static ref TRAINER_DIRECTORY_CACHE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "api_trainer_directory_cache_total",
+        "Trainer directory cache lookups by result",
+        &["result"]
+    ).unwrap();
 }
 
 // Timer utility for tracking durations
@@ -66,18 +84,70 @@ impl Default for Timer {
     }
 }
 
+// Per-request timing breakdown, collected only inside `RequestTimings::scope`
+tokio::task_local! {
+    static REQUEST_TIMINGS: RefCell<RequestTimings>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestTimings {
+    pub db_seconds: f64,
+    pub hash_seconds: f64,
+    pub validation_seconds: f64,
+    db_depth: u32,
+}
+
+impl RequestTimings {
+    /// Runs `future` with a fresh timing collector and returns what it recorded
+    pub async fn scope<F: Future>(future: F) -> (F::Output, RequestTimings) {
+        REQUEST_TIMINGS
+            .scope(RefCell::new(RequestTimings::default()), async move {
+                let output = future.await;
+                let timings = REQUEST_TIMINGS.with(|timings| timings.borrow().clone());
+                (output, timings)
+            })
+            .await
+    }
+
+    pub fn record_hash(seconds: f64) {
+        let _ = REQUEST_TIMINGS.try_with(|timings| timings.borrow_mut().hash_seconds += seconds);
+    }
+
+    pub fn record_validation(seconds: f64) {
+        let _ = REQUEST_TIMINGS.try_with(|timings| timings.borrow_mut().validation_seconds += seconds);
+    }
+
+    // Zagnieżdżone operacje (np. find_by_id wewnątrz update) liczymy tylko raz
+    fn enter_db() {
+        let _ = REQUEST_TIMINGS.try_with(|timings| timings.borrow_mut().db_depth += 1);
+    }
+
+    fn leave_db(seconds: f64) {
+        let _ = REQUEST_TIMINGS.try_with(|timings| {
+            let mut timings = timings.borrow_mut();
+            timings.db_depth -= 1;
+            if timings.db_depth == 0 {
+                timings.db_seconds += seconds;
+            }
+        });
+    }
+}
+
 // Database operations tracker
 pub struct DbMetrics;
 
 impl DbMetrics {
-    pub fn track<F, T>(operation: &str, table: &str, f: F) -> T
+    pub async fn track<F, Fut, T>(operation: &str, table: &str, f: F) -> T
     where
-        F: FnOnce() -> T,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
     {
         DB_QUERY_COUNTER.with_label_values(&[operation, table]).inc();
         let timer = Timer::new();
-        let result = f();
+        RequestTimings::enter_db();
+        let result = f().await;
         let duration = timer.elapsed_seconds();
+        RequestTimings::leave_db(duration);
         DB_QUERY_DURATION
             .with_label_values(&[operation, table])
             .observe(duration);
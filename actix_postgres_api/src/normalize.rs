@@ -0,0 +1,100 @@
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::ops::Deref;
+
+use crate::error::AppError;
+use crate::types::unknown_variant_error;
+
+// Normalizacja danych wejściowych przed walidacją: białe znaki na brzegach wartości
+// tekstowych są usuwane w jednym miejscu, więc walidatory nie muszą tego robić same
+
+// Pola, których wartości przekazujemy bez zmian (dopasowanie fragmentu nazwy)
+const UNTRIMMED_KEY_FRAGMENTS: &[&str] = &["password"];
+
+fn is_untrimmed(key: &str) -> bool {
+    let key = key.to_lowercase();
+    UNTRIMMED_KEY_FRAGMENTS.iter().any(|fragment| key.contains(fragment))
+}
+
+/// Normalizes a single value of the named field the same way request bodies are
+pub fn trim_field_value<'a>(field: &str, value: &'a str) -> &'a str {
+    if is_untrimmed(field) {
+        value
+    } else {
+        value.trim()
+    }
+}
+
+/// Trims every string value in the document, at any depth, except password fields
+pub fn trim_strings(value: &mut Value) {
+    match value {
+        Value::String(text) => {
+            let trimmed = text.trim();
+            if trimmed.len() != text.len() {
+                *text = trimmed.to_string();
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if !is_untrimmed(key) {
+                    trim_strings(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(trim_strings),
+        _ => {}
+    }
+}
+
+/// Parses a query string into `T`, trimming every value except password fields
+pub fn parse_trimmed_query<T: DeserializeOwned>(query: &str) -> Result<T, AppError> {
+    let invalid = |e: &dyn std::fmt::Display| AppError::ValidationError(format!("Invalid query string: {}", e));
+
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).map_err(|e| invalid(&e))?;
+    let trimmed: Vec<(String, String)> = pairs
+        .into_iter()
+        .map(|(key, value)| {
+            let value = trim_field_value(&key, &value).to_string();
+            (key, value)
+        })
+        .collect();
+
+    let normalized = serde_urlencoded::to_string(&trimmed).map_err(|e| invalid(&e))?;
+    let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(normalized.as_bytes()));
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        // Nieznana wartość parametru-enuma (np. ?return=all) to ten sam błąd co w treści żądania
+        unknown_variant_error(&e.path().to_string(), &e.inner().to_string()).unwrap_or_else(|| invalid(e.inner()))
+    })
+}
+
+/// Query extractor that trims parameter values before deserializing them into `T`
+pub struct TrimmedQuery<T>(pub T);
+
+impl<T> TrimmedQuery<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for TrimmedQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for TrimmedQuery<T> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            parse_trimmed_query(req.query_string())
+                .map(TrimmedQuery)
+                .map_err(Into::into),
+        )
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::Utc;
+use futures::future::BoxFuture;
+use rand::Rng;
+use sqlx::postgres::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth_utils::{hash_password, verify_password};
+use crate::config::{Config, SmsSenderKind};
+use crate::error::AppError;
+use crate::models::User;
+use crate::repository::{PhoneVerificationRepository, UserRepository};
+
+const CODE_DIGITS: usize = 6;
+
+/// Delivers text messages; implement it to plug in an SMS gateway
+pub trait SmsSender: Send + Sync {
+    fn send<'a>(&'a self, phone_number: &'a str, message: &'a str) -> BoxFuture<'a, Result<(), AppError>>;
+}
+
+/// Development sender that only logs that a message would be sent.
+/// The message itself holds the code, so it is never written to the log
+pub struct LogSmsSender;
+
+impl SmsSender for LogSmsSender {
+    fn send<'a>(&'a self, phone_number: &'a str, message: &'a str) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            tracing::info!(target: "sms", phone_number, message_len = message.len(), "SMS not sent, no gateway configured");
+            Ok(())
+        })
+    }
+}
+
+/// Sender used when no gateway is configured; every send fails with 503
+pub struct DisabledSmsSender;
+
+impl SmsSender for DisabledSmsSender {
+    fn send<'a>(&'a self, _phone_number: &'a str, _message: &'a str) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async {
+            Err(AppError::ServiceUnavailable("SMS delivery is not configured".to_string()))
+        })
+    }
+}
+
+/// The sender selected by `SMS_SENDER`
+pub fn sms_sender_from_config(config: &Config) -> Arc<dyn SmsSender> {
+    match config.sms_sender {
+        SmsSenderKind::Disabled => Arc::new(DisabledSmsSender),
+        SmsSenderKind::Log => Arc::new(LogSmsSender),
+    }
+}
+
+// Kod numeryczny z wiodącymi zerami, np. "042917"
+fn generate_code() -> String {
+    let code = rand::rng().random_range(0..10u32.pow(CODE_DIGITS as u32));
+    format!("{:0width$}", code, width = CODE_DIGITS)
+}
+
+/// Sends a fresh verification code to the user's phone, replacing any pending one.
+/// Failed attempts carry over to the new code until the pending one expires
+pub async fn send_phone_verification(
+    pool: &PgPool,
+    sender: &dyn SmsSender,
+    config: &Config,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    let user = UserRepository::new(pool.clone()).find_by_id(user_id).await?;
+    let phone_number = user
+        .phone_number
+        .ok_or_else(|| AppError::ValidationError("User has no phone number to verify".to_string()))?;
+    if user.phone_verified_at.is_some() {
+        return Err(AppError::Conflict("Phone number is already verified".to_string()));
+    }
+
+    let code = generate_code();
+    let code_hash = hash_password(&code).await?;
+
+    let verifications = PhoneVerificationRepository::new(pool.clone());
+    let replaced = verifications
+        .replace(
+            user_id,
+            &phone_number,
+            &code_hash,
+            config.phone_verification_ttl_secs,
+            config.phone_verification_resend_interval_secs,
+        )
+        .await?;
+    if !replaced {
+        // Poprzedni kod wysłano zbyt niedawno; podajemy, ile zostało do końca odstępu
+        let retry_after = match verifications.find(user_id).await? {
+            Some(pending) => {
+                let elapsed = (Utc::now() - pending.created_at).num_seconds().max(0) as u64;
+                config.phone_verification_resend_interval_secs.saturating_sub(elapsed).max(1)
+            }
+            None => 1,
+        };
+        return Err(AppError::RateLimitExceeded(retry_after));
+    }
+
+    // Kod, którego nie udało się doręczyć, nie powinien pozostać aktywny
+    let message = format!("Your verification code is {}", code);
+    if let Err(e) = sender.send(&phone_number, &message).await {
+        verifications.delete(user_id).await?;
+        return Err(e);
+    }
+
+    tracing::info!("Phone verification code sent to user {}", user_id);
+    Ok(())
+}
+
+/// Checks `code` against the pending verification and marks the phone verified
+pub async fn verify_phone(pool: &PgPool, config: &Config, user_id: Uuid, code: &str) -> Result<User, AppError> {
+    let verifications = PhoneVerificationRepository::new(pool.clone());
+
+    let Some(verification) = verifications
+        .consume_attempt(user_id, config.phone_verification_max_attempts)
+        .await?
+    else {
+        return Err(match verifications.find(user_id).await? {
+            Some(_) => AppError::TooManyAttempts("Too many incorrect codes, request a new one".to_string()),
+            None => AppError::NotFoundError("No pending phone verification".to_string()),
+        });
+    };
+
+    if verification.expires_at <= Utc::now() {
+        verifications.delete(user_id).await?;
+        return Err(AppError::ValidationError("Verification code has expired, request a new one".to_string()));
+    }
+
+    if !verify_password(code.trim(), &verification.code_hash).await? {
+        return Err(AppError::ValidationError("Incorrect verification code".to_string()));
+    }
+
+    verifications
+        .confirm(user_id, &verification.phone_number)
+        .await?
+        .ok_or_else(|| AppError::Conflict("Phone number changed after the code was sent".to_string()))
+}
@@ -0,0 +1,254 @@
+use actix_web::{web, HttpRequest};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::config::{Config, RateLimitPolicy};
+use crate::models::UserRole;
+use crate::types::ParseEnum;
+
+// Po tylu odrzuconych zapisach w ciągu doby konto zgłaszane jest jako podejrzane
+const SECURITY_EVENT_THRESHOLD: u32 = 10;
+const SECURITY_EVENT_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Nieaktywnych wywołujących usuwamy co SWEEP_INTERVAL zamiast przy każdym żądaniu;
+// powyżej MAX_TRACKED_CALLERS wpisów najdawniej aktywny wywołujący jest usuwany
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_TRACKED_CALLERS: usize = 100_000;
+
+// Wpis historii jednego wywołującego
+trait Tracked: Default {
+    fn last_seen(&self) -> Option<Instant>;
+    fn is_stale(&self, now: Instant, window: Duration) -> bool;
+}
+
+// Historie wywołujących z okresowym porządkowaniem i ograniczonym rozmiarem
+struct CallerHistory<K, V> {
+    entries: HashMap<K, V>,
+    last_sweep: Instant,
+}
+
+impl<K: Eq + Hash + Clone, V: Tracked> CallerHistory<K, V> {
+    fn new() -> Self {
+        Self { entries: HashMap::new(), last_sweep: Instant::now() }
+    }
+
+    fn entry(&mut self, key: &K, now: Instant, window: Duration) -> &mut V {
+        let is_new = !self.entries.contains_key(key);
+        if now.duration_since(self.last_sweep) >= SWEEP_INTERVAL || (is_new && self.entries.len() >= MAX_TRACKED_CALLERS) {
+            self.entries.retain(|_, entry| !entry.is_stale(now, window));
+            self.last_sweep = now;
+        }
+        if is_new && self.entries.len() >= MAX_TRACKED_CALLERS {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_seen())
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.entry(key.clone()).or_default()
+    }
+}
+
+/// Who a profile write is counted against: the signed-in caller, or the client address
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WriteCaller {
+    User(Uuid),
+    Address(String),
+}
+
+impl fmt::Display for WriteCaller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteCaller::User(id) => write!(f, "user:{}", id),
+            WriteCaller::Address(address) => write!(f, "ip:{}", address),
+        }
+    }
+}
+
+/// A write refused by [`WriteRateLimiter`]
+#[derive(Debug)]
+pub struct WriteRejection {
+    pub retry_after: Duration,
+    /// Set once per day when the caller keeps exceeding the limit; worth an audit entry
+    pub security_event: bool,
+}
+
+// Żądania jednego wywołującego w bieżącym oknie
+#[derive(Default)]
+struct RequestHistory {
+    requests: VecDeque<Instant>,
+}
+
+impl Tracked for RequestHistory {
+    fn last_seen(&self) -> Option<Instant> {
+        self.requests.back().copied()
+    }
+
+    fn is_stale(&self, now: Instant, window: Duration) -> bool {
+        self.requests.back().is_none_or(|last| now.duration_since(*last) >= window)
+    }
+}
+
+// Historia zapisów jednego wywołującego
+#[derive(Default)]
+struct WriteHistory {
+    writes: VecDeque<Instant>,
+    rejections: u32,
+    rejections_since: Option<Instant>,
+}
+
+impl Tracked for WriteHistory {
+    fn last_seen(&self) -> Option<Instant> {
+        self.writes.back().copied().max(self.rejections_since)
+    }
+
+    fn is_stale(&self, now: Instant, window: Duration) -> bool {
+        self.writes.back().is_none_or(|last| now.duration_since(*last) >= window)
+            && self.rejections_since.is_none_or(|since| now.duration_since(since) >= SECURITY_EVENT_PERIOD)
+    }
+}
+
+/// Per-caller sliding-window limit on profile writes, shared by all workers
+pub struct WriteRateLimiter {
+    max_writes: u32,
+    window: Duration,
+    history: Mutex<CallerHistory<WriteCaller, WriteHistory>>,
+}
+
+impl WriteRateLimiter {
+    pub fn new(max_writes: u32, window: Duration) -> Self {
+        Self {
+            max_writes,
+            window,
+            history: Mutex::new(CallerHistory::new()),
+        }
+    }
+
+    /// Records a write by `caller`, or says how long to wait if the limit is reached
+    pub fn check(&self, caller: &WriteCaller) -> Result<(), WriteRejection> {
+        let now = Instant::now();
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+
+        let entry = history.entry(caller, now, self.window);
+        while entry.writes.front().is_some_and(|first| now.duration_since(*first) >= self.window) {
+            entry.writes.pop_front();
+        }
+
+        if entry.writes.len() < self.max_writes as usize {
+            entry.writes.push_back(now);
+            return Ok(());
+        }
+
+        // Limit przekroczony - liczymy odrzucenia w bieżącej dobie
+        if entry.rejections_since.is_none_or(|since| now.duration_since(since) >= SECURITY_EVENT_PERIOD) {
+            entry.rejections = 0;
+            entry.rejections_since = Some(now);
+        }
+        entry.rejections += 1;
+
+        let oldest = entry.writes.front().copied().unwrap_or(now);
+        Err(WriteRejection {
+            retry_after: self.window.saturating_sub(now.duration_since(oldest)),
+            security_event: entry.rejections == SECURITY_EVENT_THRESHOLD,
+        })
+    }
+}
+
+/// Client address used to tell anonymous callers apart: the peer address, or with
+/// `trust_proxy_headers` the client reported by the proxy in `Forwarded` / `X-Forwarded-For`
+pub fn client_address(req: &HttpRequest) -> String {
+    let trust_proxy_headers = req
+        .app_data::<web::Data<Config>>()
+        .is_some_and(|config| config.trust_proxy_headers);
+    if trust_proxy_headers {
+        if let Some(address) = req.connection_info().realip_remote_addr() {
+            return address.to_string();
+        }
+    }
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Which request limit applies to a caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallerTier {
+    Anonymous,
+    Client,
+    Trainer,
+    Admin,
+}
+
+impl CallerTier {
+    /// Tier for the role claim of an access token; tokens without one count as clients
+    pub fn from_role(role: Option<&str>) -> Self {
+        match role.and_then(UserRole::from_name) {
+            Some(UserRole::Trainer) => CallerTier::Trainer,
+            _ => CallerTier::Client,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallerTier::Anonymous => "anonymous",
+            CallerTier::Client => "client",
+            CallerTier::Trainer => "trainer",
+            CallerTier::Admin => "admin",
+        }
+    }
+}
+
+/// Per-caller sliding-window limit on API requests, with a separate limit for each tier
+pub struct RequestRateLimiter {
+    policy: RateLimitPolicy,
+    history: Mutex<CallerHistory<(CallerTier, String), RequestHistory>>,
+}
+
+impl RequestRateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            policy,
+            history: Mutex::new(CallerHistory::new()),
+        }
+    }
+
+    fn limit(&self, tier: CallerTier) -> u32 {
+        match tier {
+            CallerTier::Anonymous => self.policy.anonymous,
+            CallerTier::Client => self.policy.client,
+            CallerTier::Trainer => self.policy.trainer,
+            CallerTier::Admin => self.policy.admin,
+        }
+    }
+
+    /// Records a request by `caller` (user id, client IP, ...) in `tier`, or returns how long to wait
+    pub fn check(&self, tier: CallerTier, caller: &str) -> Result<(), Duration> {
+        let max_requests = self.limit(tier);
+        if max_requests == 0 {
+            return Ok(());
+        }
+        let window = Duration::from_secs(self.policy.window_secs);
+        let now = Instant::now();
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+
+        let requests = &mut history.entry(&(tier, caller.to_string()), now, window).requests;
+        while requests.front().is_some_and(|first| now.duration_since(*first) >= window) {
+            requests.pop_front();
+        }
+
+        if requests.len() < max_requests as usize {
+            requests.push_back(now);
+            return Ok(());
+        }
+
+        let oldest = requests.front().copied().unwrap_or(now);
+        Err(window.saturating_sub(now.duration_since(oldest)))
+    }
+}
@@ -0,0 +1,99 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+// Routing odczytów: handlery GET czytają z repliki, zapisy i odczyty wewnątrz
+// operacji zapisu zawsze idą do primary
+
+/// Header that makes a request read from the primary, e.g. right after the client's own write
+pub const READ_YOUR_WRITES_HEADER: &str = "X-Read-Your-Writes";
+
+/// Connection pool of the read replica, when one is configured
+#[derive(Clone, Default)]
+pub struct ReadReplica(Option<Arc<Replica>>);
+
+struct Replica {
+    pool: PgPool,
+    retry_interval: Duration,
+    // Do tej chwili odczyty omijają replikę, która ostatnio zawiodła
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl ReadReplica {
+    pub fn new(pool: PgPool, retry_interval: Duration) -> Self {
+        Self(Some(Arc::new(Replica {
+            pool,
+            retry_interval,
+            unhealthy_until: Mutex::new(None),
+        })))
+    }
+
+    /// Creates a lazy pool for `READ_REPLICA_URL`, so an unreachable replica does not block startup
+    pub fn from_config(config: &Config) -> Result<Self, sqlx::Error> {
+        let Some(ref url) = config.read_replica_url else {
+            return Ok(Self::default());
+        };
+        let retry_interval = Duration::from_secs(config.read_replica_retry_interval_secs);
+        PgPoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .acquire_timeout(Duration::from_millis(config.read_replica_acquire_timeout_ms))
+            .connect_lazy(url)
+            .map(|pool| Self::new(pool, retry_interval))
+    }
+
+    pub fn pool(&self) -> Option<&PgPool> {
+        self.0.as_ref().map(|replica| &replica.pool)
+    }
+
+    /// The replica pool, unless none is configured or it failed within the retry interval
+    pub fn healthy_pool(&self) -> Option<&PgPool> {
+        let replica = self.0.as_ref()?;
+        let unhealthy_until = replica.unhealthy_until.lock().unwrap_or_else(|e| e.into_inner());
+        match *unhealthy_until {
+            Some(until) if Instant::now() < until => None,
+            _ => Some(&replica.pool),
+        }
+    }
+
+    /// Sends reads to the primary for the retry interval, after a connection failure
+    pub fn mark_unhealthy(&self) {
+        if let Some(ref replica) = self.0 {
+            let mut unhealthy_until = replica.unhealthy_until.lock().unwrap_or_else(|e| e.into_inner());
+            *unhealthy_until = Some(Instant::now() + replica.retry_interval);
+        }
+    }
+}
+
+/// The replica this request should read from: none when no replica is configured
+/// or the request carries the read-your-writes header
+pub struct ReadPool(ReadReplica);
+
+impl ReadPool {
+    pub fn into_inner(self) -> ReadReplica {
+        self.0
+    }
+}
+
+/// Whether the request asked to read from the primary, skipping the replica and response caches
+pub fn reads_own_writes(req: &HttpRequest) -> bool {
+    req.headers().contains_key(READ_YOUR_WRITES_HEADER)
+}
+
+impl FromRequest for ReadPool {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let replica = req
+            .app_data::<web::Data<ReadReplica>>()
+            .filter(|_| !reads_own_writes(req))
+            .map(|replica| replica.get_ref().clone())
+            .unwrap_or_default();
+        ready(Ok(ReadPool(replica)))
+    }
+}
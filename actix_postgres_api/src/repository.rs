@@ -1,39 +1,458 @@
+use crate::config::{LockoutPolicy, RoleFieldPolicy};
 use crate::error::AppError;
-use crate::models::{CreateUserRequest, UpdateUserRequest, User, LoginRequest};
-use crate::auth_utils::{hash_password, verify_password, validate_role};
-use crate::monitoring::DbMetrics;
+use crate::models::{DuplicatePair, FailedRequest, NewUser, PhoneVerification, PublicTrainer, RankedUser, RoleSpecificFields, Specialty, UserChanges, User};
+use crate::types::{Email, Username};
+use crate::auth_utils::{check_role_required_fields, enforce_role_fields, field_allowed_for_role, hash_password, verify_dummy_password, verify_password, validate_role};
+use crate::monitoring::{DbMetrics, DB_READ_ROUTING_COUNTER, LOGIN_FAILURE_COUNTER};
 use crate::logging::create_db_span;
-use sqlx::{postgres::PgPool, types::Uuid};
+use crate::replica::ReadReplica;
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use sqlx::{pool::PoolConnection, postgres::PgPool, types::Uuid, Acquire, PgExecutor, Postgres};
 use tracing::Instrument;
 
+/// Search query; `deleted_at IS NULL` matches the partial trigram indexes on
+/// username and full_name, so both ILIKE branches can use a bitmap index scan
+pub const SEARCH_USERS_SQL: &str = "SELECT * FROM users WHERE deleted_at IS NULL AND (username ILIKE $1 OR full_name ILIKE $1) ORDER BY username";
+
+/// Builds an ILIKE pattern matching `query` anywhere, with `%`, `_` and `\` taken literally
+pub fn contains_pattern(query: &str) -> String {
+    format!("%{}%", escape_like(query))
+}
+
+/// Builds an ILIKE pattern matching values that start with `query`
+pub fn prefix_pattern(query: &str) -> String {
+    format!("{}%", escape_like(query))
+}
+
+fn escape_like(query: &str) -> String {
+    query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Searches username, full name and (when `$4` is set) email, scoring each user by
+/// their best-matching field: the match quality (exact, prefix, substring or trigram
+/// similarity) times the field's weight
+pub const RANKED_SEARCH_SQL: &str = r#"
+    SELECT u.*, best.matched_on, best.score
+    FROM users u
+    CROSS JOIN LATERAL (
+        SELECT f.field AS matched_on,
+            (f.weight * CASE
+                WHEN lower(f.value) = lower($1) THEN 1.0
+                WHEN f.value ILIKE $2 THEN 0.9
+                WHEN f.value ILIKE $3 THEN 0.7
+                ELSE 0.5 * similarity(f.value, $1)
+            END)::real AS score
+        FROM (VALUES
+            ('username', u.username, 1.0),
+            ('full_name', u.full_name, 0.8),
+            ('email', CASE WHEN $4 THEN u.email END, 0.6)
+        ) AS f(field, value, weight)
+        WHERE f.value IS NOT NULL
+        ORDER BY score DESC
+        LIMIT 1
+    ) best
+    WHERE u.deleted_at IS NULL
+        AND (u.username ILIKE $3 OR u.full_name ILIKE $3 OR u.username % $1 OR u.full_name % $1
+            OR ($4 AND (u.email ILIKE $3 OR u.email % $1)))
+    ORDER BY best.score DESC, u.username
+    LIMIT 50
+"#;
+
+/// Pairs of active accounts that share a phone number or have similar email local
+/// parts / full names; local parts ignore `+tag` suffixes. Candidates come from the
+/// trigram (`%`, using `pg_trgm.similarity_threshold`) and phone digit indexes, so
+/// the query never compares every pair of accounts
+pub const DUPLICATE_PAIRS_SQL: &str = r#"
+    WITH candidates AS (
+        SELECT a.id AS first_id, b.id AS second_id
+        FROM users a
+        JOIN users b ON b.deleted_at IS NULL AND a.id < b.id
+            AND split_part(split_part(b.email, '@', 1), '+', 1) % split_part(split_part(a.email, '@', 1), '+', 1)
+        WHERE a.deleted_at IS NULL
+        UNION
+        SELECT a.id, b.id
+        FROM users a
+        JOIN users b ON b.deleted_at IS NULL AND a.id < b.id
+            AND lower(b.full_name) % lower(a.full_name)
+        WHERE a.deleted_at IS NULL
+        UNION
+        SELECT a.id, b.id
+        FROM users a
+        JOIN users b ON b.deleted_at IS NULL AND b.phone_number IS NOT NULL AND a.id < b.id
+            AND regexp_replace(b.phone_number, '\D', '', 'g') = regexp_replace(a.phone_number, '\D', '', 'g')
+        WHERE a.deleted_at IS NULL AND a.phone_number IS NOT NULL
+            AND regexp_replace(a.phone_number, '\D', '', 'g') <> ''
+    )
+    SELECT * FROM (
+        SELECT c.first_id, c.second_id,
+            COALESCE(
+                regexp_replace(a.phone_number, '\D', '', 'g') <> ''
+                AND regexp_replace(a.phone_number, '\D', '', 'g') = regexp_replace(b.phone_number, '\D', '', 'g'),
+                false
+            ) AS phone_match,
+            similarity(
+                split_part(split_part(a.email, '@', 1), '+', 1),
+                split_part(split_part(b.email, '@', 1), '+', 1)
+            ) AS email_similarity,
+            similarity(lower(a.full_name), lower(b.full_name)) AS name_similarity
+        FROM candidates c
+        JOIN users a ON a.id = c.first_id
+        JOIN users b ON b.id = c.second_id
+    ) pairs
+    WHERE phone_match OR email_similarity >= $1 OR name_similarity >= $2
+"#;
+
+/// Sets the `%` threshold for [`DUPLICATE_PAIRS_SQL`] in the current transaction
+pub const DUPLICATE_THRESHOLD_SQL: &str = "SELECT set_config('pg_trgm.similarity_threshold', $1::text, true)";
+
+// Warunek publicznego profilu odpowiada indeksowi users_public_trainers_idx
+// Specjalizacje dołączane są jako tablica, uporządkowana alfabetycznie
+const PUBLIC_TRAINERS_SQL: &str = r#"
+    SELECT users.*, ARRAY(SELECT ts.specialty::text FROM trainer_specialties ts WHERE ts.user_id = users.id ORDER BY ts.specialty) AS specialties
+    FROM users
+    WHERE public_profile AND role = 'trainer' AND active AND deleted_at IS NULL
+      AND ($3::text IS NULL OR EXISTS (SELECT 1 FROM trainer_specialties ts WHERE ts.user_id = users.id AND ts.specialty = $3))
+    ORDER BY username LIMIT $1 OFFSET $2
+"#;
+const COUNT_PUBLIC_TRAINERS_SQL: &str = r#"
+    SELECT COUNT(*) FROM users
+    WHERE public_profile AND role = 'trainer' AND active AND deleted_at IS NULL
+      AND ($1::text IS NULL OR EXISTS (SELECT 1 FROM trainer_specialties ts WHERE ts.user_id = users.id AND ts.specialty = $1))
+"#;
+const PUBLIC_TRAINER_SQL: &str = r#"
+    SELECT users.*, ARRAY(SELECT ts.specialty::text FROM trainer_specialties ts WHERE ts.user_id = users.id ORDER BY ts.specialty) AS specialties
+    FROM users
+    WHERE username = $1 AND public_profile AND role = 'trainer' AND active AND deleted_at IS NULL
+"#;
+
+// Slugi spoza słownika specjalizacji, w kolejności podania
+const UNKNOWN_SPECIALTIES_SQL: &str = r#"
+    SELECT requested.slug FROM unnest($1::text[]) WITH ORDINALITY AS requested (slug, position)
+    WHERE NOT EXISTS (SELECT 1 FROM specialties WHERE specialties.slug = requested.slug)
+    ORDER BY requested.position
+"#;
+
+// Nowe hasło unieważnia wszystkie dotychczasowe tokeny, tak samo jak w PUT /users/{id}
+const CHANGE_PASSWORD_SQL: &str = r#"
+    UPDATE users
+    SET password_hash = $2, password_change_required = false, token_version = token_version + 1, updated_at = NOW()
+    WHERE id = $1 AND deleted_at IS NULL
+    RETURNING *
+"#;
+
+const ROLE_ASSIGNMENTS_SQL: &str = "SELECT * FROM users WHERE role_expires_at IS NOT NULL AND deleted_at IS NULL AND ($1::timestamptz IS NULL OR role_expires_at < $1) ORDER BY role_expires_at, id";
+
+// Zmiana roli unieważnia tokeny tak samo jak przy PUT /users/{id}
+const EXPIRE_ROLES_SQL: &str = r#"
+    WITH expired AS (
+        SELECT id, role FROM users
+        WHERE role_expires_at <= NOW() AND deleted_at IS NULL
+        FOR UPDATE
+    ), updated AS (
+        UPDATE users u
+        SET role = $1, role_expires_at = NULL, updated_at = NOW(),
+            token_version = CASE WHEN u.role <> $1 THEN u.token_version + 1 ELSE u.token_version END,
+            bio = CASE WHEN $2 THEN u.bio END,
+            hourly_rate = CASE WHEN $3 THEN u.hourly_rate END,
+            fitness_goals = CASE WHEN $4 THEN u.fitness_goals END
+        FROM expired e
+        WHERE u.id = e.id
+        RETURNING u.id, e.role AS previous_role
+    ), dropped_specialties AS (
+        DELETE FROM trainer_specialties
+        WHERE NOT $5 AND user_id IN (SELECT id FROM updated)
+    ), logged AS (
+        INSERT INTO audit_log (action, actor, details)
+        SELECT 'role_expired', 'system', jsonb_build_object('user_id', id, 'from', previous_role, 'to', $1::text)
+        FROM updated
+    )
+    SELECT COUNT(*) FROM updated
+"#;
+
+// Trwale usuwa konta usunięte dawniej niż $1 dni. Kody weryfikacji telefonu znikają
+// przez ON DELETE CASCADE, a z wpisów audytu scaleń usuwamy dane osobowe konta
+const PURGE_DELETED_USERS_SQL: &str = r#"
+    WITH purged AS (
+        DELETE FROM users
+        WHERE deleted_at < NOW() - make_interval(days => $1)
+        RETURNING id
+    ), scrubbed AS (
+        UPDATE audit_log
+        SET details = details - 'merged_username' - 'merged_email'
+        WHERE action = 'users_merged' AND (details->>'merged_id')::uuid IN (SELECT id FROM purged)
+    )
+    SELECT COUNT(*) FROM purged
+"#;
+
+// Nieudane logowanie: po $2 próbach z rzędu konto jest blokowane na $3 * 2^n sekund
+// (maksymalnie $5), gdzie n to liczba blokad w oknie $4 sekund od ostatniej blokady
+const RECORD_FAILED_LOGIN_SQL: &str = r#"
+    WITH current AS (
+        SELECT id, failed_login_attempts + 1 AS attempts,
+               CASE WHEN last_lockout_at > NOW() - make_interval(secs => $4) THEN lockout_count ELSE 0 END AS prior_lockouts
+        FROM users
+        WHERE id = $1
+        FOR UPDATE
+    )
+    UPDATE users u
+    SET failed_login_attempts = CASE WHEN c.attempts >= $2 THEN 0 ELSE c.attempts END,
+        lockout_count = CASE WHEN c.attempts >= $2 THEN c.prior_lockouts + 1 ELSE u.lockout_count END,
+        last_lockout_at = CASE WHEN c.attempts >= $2 THEN NOW() ELSE u.last_lockout_at END,
+        locked_until = CASE
+            WHEN c.attempts >= $2 THEN NOW() + make_interval(secs => LEAST($3 * power(2, LEAST(c.prior_lockouts, 30)), $5))
+            ELSE u.locked_until
+        END
+    FROM current c
+    WHERE u.id = c.id
+    RETURNING u.*
+"#;
+
+// Jedno zapytanie, bo executor może być użyty tylko raz; specjalizacje zapisuje CTE
+const INSERT_USER_SQL: &str = r#"
+    WITH inserted AS (
+        INSERT INTO users (id, username, email, password_hash, full_name, phone_number, role, role_expires_at,
+                           bio, hourly_rate, fitness_goals)
+        VALUES (COALESCE($1, gen_random_uuid()), $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING *
+    ), linked AS (
+        INSERT INTO trainer_specialties (user_id, specialty)
+        SELECT inserted.id, specialty FROM inserted, unnest($12::text[]) AS specialty
+    )
+    SELECT * FROM inserted
+"#;
+
+const UPDATE_USER_SQL: &str = r#"
+    UPDATE users
+    SET username = $1, email = $2, password_hash = $3, full_name = $4,
+        phone_number = $5, active = $6, role = $7, updated_at = NOW(),
+        token_version = CASE WHEN $9 THEN token_version + 1 ELSE token_version END,
+        role_expires_at = $10, public_profile = $11,
+        bio = $12, hourly_rate = $13, fitness_goals = $14,
+        password_change_required = password_change_required AND NOT $15,
+        phone_verified_at = CASE WHEN phone_number IS DISTINCT FROM $5 THEN NULL ELSE phone_verified_at END
+    WHERE id = $8 AND deleted_at IS NULL
+    RETURNING *
+"#;
+
+// Udane logowanie zeruje licznik prób; eskalację blokad dopiero po upływie okna
+const RECORD_SUCCESSFUL_LOGIN_SQL: &str = r#"
+    UPDATE users
+    SET failed_login_attempts = 0, locked_until = NULL,
+        lockout_count = CASE WHEN last_lockout_at > NOW() - make_interval(secs => $2) THEN lockout_count ELSE 0 END
+    WHERE id = $1
+"#;
+
+// Nowy kod weryfikacyjny; licznik prób przechodzi na nowy kod, dopóki poprzedni nie wygasł,
+// a kod młodszy niż $5 sekund blokuje wysłanie kolejnego
+const REPLACE_PHONE_VERIFICATION_SQL: &str = r#"
+    INSERT INTO phone_verifications (user_id, phone_number, code_hash, expires_at)
+    VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+    ON CONFLICT (user_id) DO UPDATE
+    SET phone_number = EXCLUDED.phone_number, code_hash = EXCLUDED.code_hash,
+        attempts = CASE WHEN phone_verifications.expires_at > NOW() THEN phone_verifications.attempts ELSE 0 END,
+        expires_at = EXCLUDED.expires_at, created_at = NOW()
+    WHERE phone_verifications.created_at <= NOW() - make_interval(secs => $5)
+"#;
+
+/// Why a login was rejected; logged and counted server-side, never shown to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginFailureReason {
+    UnknownUser,
+    WrongPassword,
+    AccountLocked,
+    AccountInactive,
+}
+
+impl LoginFailureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginFailureReason::UnknownUser => "unknown_user",
+            LoginFailureReason::WrongPassword => "wrong_password",
+            LoginFailureReason::AccountLocked => "account_locked",
+            LoginFailureReason::AccountInactive => "account_inactive",
+        }
+    }
+}
+
+// Błąd logowania; powód odrzucenia zostaje po stronie serwera
+#[derive(Debug)]
+pub enum LoginError {
+    Rejected(LoginFailureReason),
+    Other(AppError),
+}
+
+impl From<AppError> for LoginError {
+    fn from(error: AppError) -> Self {
+        LoginError::Other(error)
+    }
+}
+
+impl LoginError {
+    /// Error reported to the client; every rejection looks the same, so accounts cannot be enumerated
+    pub fn into_app_error(self) -> AppError {
+        match self {
+            LoginError::Rejected(_) => AppError::ValidationError("Invalid credentials".to_string()),
+            LoginError::Other(e) => e,
+        }
+    }
+}
+
+fn login_rejected(reason: LoginFailureReason, email: &Email) -> LoginError {
+    tracing::warn!(target: "security", reason = reason.as_str(), email = %email, "Login rejected");
+    LOGIN_FAILURE_COUNTER.with_label_values(&[reason.as_str()]).inc();
+    LoginError::Rejected(reason)
+}
+
 pub struct UserRepository {
     pool: PgPool,
+    replica: ReadReplica,  // Odczyty z handlerów GET; zapisy zawsze idą do `pool`
+}
+
+// Błąd zapisu pojedynczego wiersza
+#[derive(Debug)]
+pub enum InsertError {
+    UniqueViolation { field: &'static str },  // Pole, którego dotyczy naruszony indeks unikalny
+    Other(AppError),
+}
+
+impl InsertError {
+    /// Error reported to the client for a failed insert
+    pub fn into_app_error(self) -> AppError {
+        match self {
+            InsertError::UniqueViolation { field: "id" } => {
+                AppError::Conflict("A user with this id already exists".to_string())
+            }
+            InsertError::UniqueViolation { field: "email" } => {
+                AppError::ValidationError("Email is already in use".to_string())
+            }
+            InsertError::UniqueViolation { .. } => {
+                AppError::ValidationError("Username is already in use".to_string())
+            }
+            InsertError::Other(e) => e,
+        }
+    }
+}
+
+// Mapowanie nazw indeksów unikalnych na pola żądania
+fn unique_violation_field(error: &sqlx::Error) -> Option<&'static str> {
+    let db_error = error.as_database_error()?;
+    if db_error.code().as_deref() != Some("23505") {
+        return None;
+    }
+    match db_error.constraint()? {
+        "users_username_active_key" => Some("username"),
+        "users_email_active_key" => Some("email"),
+        "users_pkey" => Some("id"),
+        _ => None,
+    }
+}
+
+// Wspólny INSERT dla pojedynczego i masowego tworzenia użytkowników; hasło jest
+// hashowane wcześniej, poza spanem zapytania, żeby bcrypt nie liczył się jako czas bazy
+async fn insert_user<'e, E>(executor: E, user: &NewUser, password_hash: &str) -> Result<User, InsertError>
+where
+    E: PgExecutor<'e>,
+{
+    // Ustaw domyślną rolę client, jeśli nie podano
+    let role = user.role.as_deref().unwrap_or("client");
+    
+    sqlx::query_as::<_, User>(INSERT_USER_SQL)
+    .bind(user.id)
+    .bind(user.username.as_str())
+    .bind(user.email.as_str())
+    .bind(password_hash)
+    .bind(&user.full_name)
+    .bind(user.phone_number.as_ref().map(|phone| phone.as_str()))
+    .bind(role)
+    .bind(user.role_expires_at)
+    .bind(&user.role_fields.bio)
+    .bind(user.role_fields.hourly_rate)
+    .bind(&user.role_fields.fitness_goals)
+    .bind(user.role_fields.specialties.as_deref().unwrap_or_default())
+    .fetch_one(executor)
+    .await
+    .map_err(|e| match unique_violation_field(&e) {
+        Some(field) => InsertError::UniqueViolation { field },
+        None => InsertError::Other(AppError::from(e)),
+    })
+}
+
+// Identyfikatory, dla których sprawdzamy unikalność
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Identifier<'a> {
+    Username(&'a Username),
+    Email(&'a Email),
+}
+
+// Wynik sprawdzenia, czy identyfikator jest zajęty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentifierStatus {
+    Available,
+    InUse,                // Zajęty przez aktywne konto
+    HeldByDeletedAccount, // Używany tylko przez konto usunięte (soft delete)
 }
 
 impl UserRepository {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self { pool, replica: ReadReplica::default() }
+    }
+
+    /// Sends read-only queries to `replica` when set, falling back to the primary if it is unavailable
+    pub fn with_replica(mut self, replica: ReadReplica) -> Self {
+        self.replica = replica;
+        self
+    }
+
+    // Zapytanie tylko do odczytu. Trafia do repliki, chyba że niedawno zawiodła;
+    // błąd połączenia z repliką (także w trakcie zapytania) wyłącza ją na chwilę
+    // i to samo zapytanie jest powtarzane na primary
+    async fn read<T, F, Fut>(&self, query: F) -> Result<T, AppError>
+    where
+        F: Fn(PoolConnection<Postgres>) -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        if let Some(replica) = self.replica.healthy_pool() {
+            let result = match replica.acquire().await {
+                Ok(connection) => query(connection).await,
+                Err(e) => Err(AppError::from(e)),
+            };
+            match result {
+                Err(AppError::DatabaseUnavailable(e)) => {
+                    tracing::warn!("Read replica unavailable, reading from the primary: {}", e);
+                    self.replica.mark_unhealthy();
+                    DB_READ_ROUTING_COUNTER.with_label_values(&["primary_fallback"]).inc();
+                }
+                result => {
+                    DB_READ_ROUTING_COUNTER.with_label_values(&["replica"]).inc();
+                    return result;
+                }
+            }
+        } else if self.replica.pool().is_some() {
+            DB_READ_ROUTING_COUNTER.with_label_values(&["replica_unhealthy"]).inc();
+        }
+        query(self.pool.acquire().await?).await
     }
 
     pub async fn find_all(&self) -> Result<Vec<User>, AppError> {
         // Create a span for this database operation
         let span = create_db_span(
             "find_all_users",
-            "SELECT * FROM users ORDER BY created_at DESC",
+            "SELECT * FROM users WHERE deleted_at IS NULL ORDER BY created_at DESC",
             "None",
         );
         
         // Track DB metrics and wrap in a span
-        DbMetrics::track("SELECT", "users", || async {
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
             let users = sqlx::query_as::<_, User>(
-                "SELECT * FROM users ORDER BY created_at DESC"
+                "SELECT * FROM users WHERE deleted_at IS NULL ORDER BY created_at DESC"
             )
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *connection)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
 
             Ok(users)
-        }).instrument(span).await
+        })).instrument(span).await
     }
 
     pub async fn find_by_id(&self, id: Uuid) -> Result<User, AppError> {
@@ -41,134 +460,235 @@ impl UserRepository {
         let params = format!("id={}", id);
         let span = create_db_span(
             "find_user_by_id",
-            "SELECT * FROM users WHERE id = $1",
+            "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL",
             &params,
         );
         
-        DbMetrics::track("SELECT", "users", || async {
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
             let user = sqlx::query_as::<_, User>(
-                "SELECT * FROM users WHERE id = $1"
+                "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL"
             )
             .bind(id)
-            .fetch_optional(&self.pool)
+            .fetch_optional(&mut *connection)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
 
             user.ok_or_else(|| AppError::NotFoundError(format!("User with id {} not found", id)))
-        }).instrument(span).await
+        })).instrument(span).await
     }
 
-    pub async fn find_by_email(&self, email: &str) -> Result<User, AppError> {
+    pub async fn find_by_email(&self, email: &Email) -> Result<User, AppError> {
         let params = format!("email={}", email);
         let span = create_db_span(
             "find_user_by_email",
-            "SELECT * FROM users WHERE email = $1",
+            "SELECT * FROM users WHERE lower(email) = $1 AND deleted_at IS NULL",
             &params,
         );
         
         DbMetrics::track("SELECT", "users", || async {
             let user = sqlx::query_as::<_, User>(
-                "SELECT * FROM users WHERE email = $1"
+                "SELECT * FROM users WHERE lower(email) = $1 AND deleted_at IS NULL"
             )
-            .bind(email)
+            .bind(email.as_str())
             .fetch_optional(&self.pool)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
 
             user.ok_or_else(|| AppError::NotFoundError(format!("User with email {} not found", email)))
         }).instrument(span).await
     }
 
-    pub async fn create(&self, user: CreateUserRequest) -> Result<User, AppError> {
+    pub async fn create(&self, user: NewUser) -> Result<User, AppError> {
+        let password_hash = hash_password(&user.password).await?;
+        
         let params = format!("username={}, email={}", user.username, user.email);
-        let span = create_db_span(
-            "create_user",
-            "INSERT INTO users (username, email, password_hash, full_name, phone_number, role) VALUES ($1, $2, $3, $4, $5, $6)",
-            &params,
-        );
+        let span = create_db_span("create_user", INSERT_USER_SQL, &params);
+        
+        DbMetrics::track("INSERT", "users", || async {
+            // Wyścig z równoległą rejestracją - zgłaszamy jak zwykły konflikt
+            insert_user(&self.pool, &user, &password_hash).await.map_err(InsertError::into_app_error)
+        }).instrument(span).await
+    }
+    
+    // Zapisuje każdy wiersz niezależnie - błąd jednego nie przerywa pozostałych
+    pub async fn create_each(&self, users: Vec<NewUser>) -> Vec<Result<User, InsertError>> {
+        let mut password_hashes = Vec::with_capacity(users.len());
+        for user in &users {
+            password_hashes.push(hash_password(&user.password).await);
+        }
+        
+        let params = format!("rows={}", users.len());
+        let span = create_db_span("create_users_per_row", INSERT_USER_SQL, &params);
         
         DbMetrics::track("INSERT", "users", || async {
-            // Hashuj hasło przed zapisaniem
-            let password_hash = hash_password(&user.password)?;
+            let mut results = Vec::with_capacity(users.len());
+            for (user, password_hash) in users.iter().zip(password_hashes) {
+                let result = match password_hash {
+                    Ok(password_hash) => insert_user(&self.pool, user, &password_hash).await,
+                    Err(e) => Err(InsertError::Other(e)),
+                };
+                results.push(result);
+            }
+            results
+        }).instrument(span).await
+    }
+    
+    // Zapisuje wszystkie wiersze w jednej transakcji; przy błędzie zwraca indeks
+    // wiersza, który go spowodował, a transakcja jest wycofywana
+    pub async fn create_all(&self, users: Vec<NewUser>) -> Result<Vec<User>, (usize, InsertError)> {
+        // Hashujemy przed otwarciem transakcji, żeby bcrypt nie trzymał jej otwartej
+        let mut password_hashes = Vec::with_capacity(users.len());
+        for (index, user) in users.iter().enumerate() {
+            let password_hash = hash_password(&user.password).await.map_err(|e| (index, InsertError::Other(e)))?;
+            password_hashes.push(password_hash);
+        }
+        
+        let params = format!("rows={}", users.len());
+        let span = create_db_span("create_users_atomic", INSERT_USER_SQL, &params);
+        
+        DbMetrics::track("INSERT", "users", || async {
+            let mut tx = self.pool.begin().await
+                .map_err(|e| (0, InsertError::Other(AppError::from(e))))?;
             
-            // Ustaw domyślną rolę client, jeśli nie podano
-            let role = match user.role {
-                Some(role) => role,
-                None => "client".to_string()
-            };
+            let mut created = Vec::with_capacity(users.len());
+            for (index, (user, password_hash)) in users.iter().zip(&password_hashes).enumerate() {
+                let user = insert_user(&mut *tx, user, password_hash).await.map_err(|e| (index, e))?;
+                created.push(user);
+            }
             
-            let user = sqlx::query_as::<_, User>(
-                r#"
-                INSERT INTO users (username, email, password_hash, full_name, phone_number, role)
-                VALUES ($1, $2, $3, $4, $5, $6)
-                RETURNING *
-                "#
-            )
-            .bind(&user.username)
-            .bind(&user.email)
-            .bind(&password_hash)
-            .bind(&user.full_name)
-            .bind(&user.phone_number)
-            .bind(&role)
-            .fetch_one(&self.pool)
-            .await
-            .map_err(AppError::DatabaseError)?;
-
-            Ok(user)
+            tx.commit().await
+                .map_err(|e| (0, InsertError::Other(AppError::from(e))))?;
+            Ok(created)
         }).instrument(span).await
     }
 
-    pub async fn update(&self, id: Uuid, user: UpdateUserRequest) -> Result<User, AppError> {
+    /// Applies `user` to the account; role-specific fields are checked against the
+    /// role the account will have, under a row lock, and handled according to `role_field_policy`
+    pub async fn update(&self, id: Uuid, mut user: UserChanges, role_field_policy: RoleFieldPolicy) -> Result<User, AppError> {
+        // Nowe hasło hashujemy przed otwarciem transakcji i poza spanem zapytania, żeby bcrypt
+        // nie trzymał blokady wiersza ani nie liczył się jako czas bazy; wymóg zmiany hasła jest przy tym zdejmowany
+        let changes_password = user.password.is_some();
+        let new_password_hash = match user.password.take() {
+            Some(new_password) => Some(hash_password(&new_password).await?),
+            None => None,
+        };
+        
         let params = format!("id={}", id);
-        let span = create_db_span(
-            "update_user",
-            "UPDATE users SET ... WHERE id = $1",
-            &params,
-        );
+        let span = create_db_span("update_user", UPDATE_USER_SQL, &params);
         
         DbMetrics::track("UPDATE", "users", || async {
-            // Najpierw sprawdzamy, czy użytkownik istnieje
-            let existing = self.find_by_id(id).await?;
+            // Wiersz jest zablokowany do końca transakcji, więc wszystkie sprawdzenia poniżej
+            // (stary numer, pola wymagane i dozwolone dla roli) dotyczą stanu, który nadpisuje UPDATE
+            let mut tx = self.pool.begin().await?;
+            let existing = sqlx::query_as::<_, User>(
+                "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"
+            )
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| AppError::NotFoundError(format!("User with id {} not found", id)))?;
+            
+            // Numer w starym formacie czytamy bez błędu, ale edycja profilu musi go poprawić
+            // lub usunąć; zmiany stanu konta (aktywność, hasło, rola) są dozwolone bez tego
+            if user.phone_number.is_none() && user.edits_profile() && existing.phone_needs_update() {
+                return Err(AppError::ValidationError(
+                    "Stored phone number is in a legacy format; include a valid phone_number (or null to remove it) in this update".to_string()
+                ));
+            }
+
+            // Pola wymagane przez rolę sprawdzamy na wartościach po zmianie, gdy zmienia się
+            // rola albo któreś z tych pól - np. zmiana roli na trenera wymaga telefonu
+            let touches_required_fields = user.role.is_some() || user.full_name.is_some() || user.phone_number.is_some();
 
             // Przygotowanie wartości do aktualizacji
-            let username = user.username.unwrap_or(existing.username);
-            let email = user.email.unwrap_or(existing.email);
+            let username = user.username.map(String::from).unwrap_or(existing.username);
+            let email = user.email.map(String::from).unwrap_or(existing.email);
             let full_name = user.full_name.unwrap_or(existing.full_name);
-            let phone_number = user.phone_number.or(existing.phone_number);
+            let phone_number = match user.phone_number {
+                Some(phone_number) => phone_number.map(String::from),
+                None => existing.phone_number,
+            };
             let active = user.active.unwrap_or(existing.active);
+            
+            // Zmiana hasła lub roli unieważnia wszystkie wydane tokeny użytkownika
+            let role_changed = user.role.as_ref().is_some_and(|role| *role != existing.role);
+            let revoke_tokens = changes_password || role_changed;
             let role = user.role.unwrap_or(existing.role);
+            if touches_required_fields {
+                check_role_required_fields(
+                    &role,
+                    [
+                        ("full_name", Some(full_name.as_str())),
+                        ("phone_number", phone_number.as_deref()),
+                    ],
+                )
+                .map_err(|(_, e)| e)?;
+            }
+            let role_expires_at = user.role_expires_at.unwrap_or(existing.role_expires_at);
+            let public_profile = user.public_profile.unwrap_or(existing.public_profile);
             
-            // Aktualizacja hasła tylko jeśli podano nowe
-            let password_hash = match user.password {
-                Some(new_password) => hash_password(&new_password)?,
-                None => existing.password_hash
+            // Podane pola profilu innej roli są odrzucane lub pomijane, zależnie od konfiguracji
+            enforce_role_fields(&role, &mut user.role_fields, role_field_policy).map_err(|(_, e)| e)?;
+            
+            // Po zmianie roli pola profilu poprzedniej roli są czyszczone
+            let mut role_fields = RoleSpecificFields {
+                bio: user.role_fields.bio.or(existing.bio),
+                hourly_rate: user.role_fields.hourly_rate.or(existing.hourly_rate),
+                fitness_goals: user.role_fields.fitness_goals.or(existing.fitness_goals),
+                specialties: None,  // Osobna tabela, zapisywana niżej
             };
+            for field in role_fields.supplied() {
+                if !field_allowed_for_role(field, &role) {
+                    role_fields.clear(field);
+                }
+            }
+            let keeps_specialties = field_allowed_for_role("specialties", &role);
+            let specialties = user.role_fields.specialties.filter(|_| keeps_specialties);
+            let drop_specialties = role_changed && !keeps_specialties;
+            
+            let password_hash = new_password_hash.unwrap_or(existing.password_hash);
 
             tracing::debug!(
                 "Updating user: id={}, username={}, email={}, active={}, role={}",
                 id, username, email, active, role
             );
 
-            let updated_user = sqlx::query_as::<_, User>(
-                r#"
-                UPDATE users
-                SET username = $1, email = $2, password_hash = $3, full_name = $4, 
-                    phone_number = $5, active = $6, role = $7, updated_at = NOW()
-                WHERE id = $8
-                RETURNING *
-                "#
-            )
+            let updated_user = sqlx::query_as::<_, User>(UPDATE_USER_SQL)
             .bind(&username)
             .bind(&email)
             .bind(&password_hash)
             .bind(&full_name)
             .bind(&phone_number)
-            .bind(&active)
+            .bind(active)
             .bind(&role)
             .bind(id)
-            .fetch_one(&self.pool)
+            .bind(revoke_tokens)
+            .bind(role_expires_at)
+            .bind(public_profile)
+            .bind(&role_fields.bio)
+            .bind(role_fields.hourly_rate)
+            .bind(&role_fields.fitness_goals)
+            .bind(changes_password)
+            .fetch_one(&mut *tx)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
+            
+            // Podana lista zastępuje poprzednią; po zmianie roli na inną niż trener jest usuwana
+            if specialties.is_some() || drop_specialties {
+                sqlx::query("DELETE FROM trainer_specialties WHERE user_id = $1")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            if let Some(specialties) = specialties {
+                sqlx::query("INSERT INTO trainer_specialties (user_id, specialty) SELECT $1, unnest($2::text[])")
+                    .bind(id)
+                    .bind(&specialties)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            
+            tx.commit().await?;
 
             Ok(updated_user)
         }).instrument(span).await
@@ -178,7 +698,7 @@ impl UserRepository {
         let params = format!("id={}", id);
         let span = create_db_span(
             "delete_user",
-            "DELETE FROM users WHERE id = $1",
+            "UPDATE users SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
             &params,
         );
         
@@ -186,46 +706,93 @@ impl UserRepository {
             // Najpierw sprawdzamy, czy użytkownik istnieje
             let _ = self.find_by_id(id).await?;
 
-            tracing::info!("Deleting user with id={}", id);
+            tracing::info!("Soft-deleting user with id={}", id);
 
-            sqlx::query("DELETE FROM users WHERE id = $1")
+            sqlx::query("UPDATE users SET deleted_at = NOW(), updated_at = NOW() WHERE id = $1 AND deleted_at IS NULL")
                 .bind(id)
                 .execute(&self.pool)
                 .await
-                .map_err(AppError::DatabaseError)?;
+                .map_err(AppError::from)?;
 
             Ok(())
         }).instrument(span).await
     }
     
-    pub async fn authenticate(&self, login: LoginRequest) -> Result<User, AppError> {
-        let params = format!("email={}", login.email);
-        let span = create_db_span(
-            "authenticate_user",
-            "SELECT * FROM users WHERE email = $1",
-            &params,
-        );
-        
-        DbMetrics::track("SELECT", "users", || async {
-            // Znajdź użytkownika po emailu
-            let user = self.find_by_email(&login.email).await?;
-            
-            // Zweryfikuj hasło
-            let is_valid = verify_password(&login.password, &user.password_hash)?;
-            
-            if !is_valid {
-                tracing::warn!("Authentication failed for user: {}", login.email);
-                return Err(AppError::ValidationError("Invalid credentials".to_string()));
+    // Każde zapytanie ma własny span; weryfikacja hasła bcryptem odbywa się między nimi,
+    // więc nie jest raportowana jako czas bazy ani jako wolne zapytanie
+    pub async fn authenticate(&self, email: &Email, password: &str, lockout: &LockoutPolicy) -> Result<User, LoginError> {
+        // Znajdź użytkownika po emailu; dla nieznanego konta też liczymy hash, żeby czas odpowiedzi go nie zdradzał
+        let user = match self.find_by_email(email).await {
+            Ok(user) => user,
+            Err(AppError::NotFoundError(_)) => {
+                verify_dummy_password(password).await?;
+                return Err(login_rejected(LoginFailureReason::UnknownUser, email));
             }
-            
-            // Jeśli konto jest nieaktywne, zwróć błąd
-            if !user.active {
-                tracing::warn!("Attempt to log in to inactive account: {}", login.email);
-                return Err(AppError::ValidationError("Account is inactive".to_string()));
+            Err(e) => return Err(e.into()),
+        };
+        
+        // Zablokowanemu kontu nawet poprawne hasło nie pomaga, ale hash i tak liczymy,
+        // żeby czas odpowiedzi nie zdradzał blokady
+        if user.locked_until.is_some_and(|until| until > Utc::now()) {
+            verify_password(password, &user.password_hash).await?;
+            return Err(login_rejected(LoginFailureReason::AccountLocked, email));
+        }
+        
+        // Zweryfikuj hasło
+        let is_valid = verify_password(password, &user.password_hash).await?;
+        
+        if !is_valid {
+            if lockout.max_failed_attempts > 0 {
+                let user = self.record_failed_login(user.id, lockout).await?;
+                if let Some(locked_until) = user.locked_until.filter(|until| *until > Utc::now()) {
+                    tracing::warn!("Account {} locked until {} after repeated failed logins", email, locked_until);
+                }
             }
-            
-            tracing::info!("User authenticated successfully: {}", login.email);
-            Ok(user)
+            return Err(login_rejected(LoginFailureReason::WrongPassword, email));
+        }
+        
+        // Nieaktywne konto zgłaszamy dopiero po poprawnym haśle
+        if !user.active {
+            return Err(login_rejected(LoginFailureReason::AccountInactive, email));
+        }
+        
+        if user.failed_login_attempts > 0 || user.lockout_count > 0 {
+            self.record_successful_login(user.id, lockout).await?;
+        }
+        
+        tracing::info!("User authenticated successfully: {}", email);
+        Ok(user)
+    }
+    
+    async fn record_successful_login(&self, id: Uuid, lockout: &LockoutPolicy) -> Result<(), AppError> {
+        let params = format!("id={}", id);
+        let span = create_db_span("record_successful_login", RECORD_SUCCESSFUL_LOGIN_SQL, &params);
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            sqlx::query(RECORD_SUCCESSFUL_LOGIN_SQL)
+                .bind(id)
+                .bind(lockout.escalation_window_secs as f64)
+                .execute(&self.pool)
+                .await
+                .map_err(AppError::from)?;
+            Ok(())
+        }).instrument(span).await
+    }
+    
+    async fn record_failed_login(&self, id: Uuid, lockout: &LockoutPolicy) -> Result<User, AppError> {
+        let params = format!("id={}", id);
+        let span = create_db_span("record_failed_login", RECORD_FAILED_LOGIN_SQL, &params);
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            sqlx::query_as::<_, User>(RECORD_FAILED_LOGIN_SQL)
+                .bind(id)
+                .bind(lockout.max_failed_attempts as i32)
+                .bind(lockout.base_lockout_secs as f64)
+                .bind(lockout.escalation_window_secs as f64)
+                .bind(lockout.max_lockout_secs as f64)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(AppError::from)
         }).instrument(span).await
     }
     
@@ -233,26 +800,331 @@ impl UserRepository {
         let params = format!("role={}", role);
         let span = create_db_span(
             "find_users_by_role",
-            "SELECT * FROM users WHERE role = $1 ORDER BY created_at DESC",
+            "SELECT * FROM users WHERE role = $1 AND deleted_at IS NULL ORDER BY created_at DESC",
             &params,
         );
         
-        DbMetrics::track("SELECT", "users", || async {
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
             // Waliduj rolę
             let valid_role = validate_role(role)?;
             
             tracing::debug!("Finding users with role: {}", valid_role);
 
             let users = sqlx::query_as::<_, User>(
-                "SELECT * FROM users WHERE role = $1 ORDER BY created_at DESC"
+                "SELECT * FROM users WHERE role = $1 AND deleted_at IS NULL ORDER BY created_at DESC"
             )
             .bind(&valid_role)
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *connection)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
             
             tracing::debug!("Found {} users with role '{}'", users.len(), valid_role);
             Ok(users)
+        })).instrument(span).await
+    }
+    
+    // Podbija wersję tokenów, unieważniając wszystkie wydane dotąd tokeny użytkownika
+    pub async fn bump_token_version(&self, id: Uuid) -> Result<i32, AppError> {
+        let params = format!("id={}", id);
+        let span = create_db_span(
+            "bump_token_version",
+            "UPDATE users SET token_version = token_version + 1 WHERE id = $1 AND deleted_at IS NULL RETURNING token_version",
+            &params,
+        );
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            let version = sqlx::query_scalar::<_, i32>(
+                "UPDATE users SET token_version = token_version + 1 WHERE id = $1 AND deleted_at IS NULL RETURNING token_version"
+            )
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(AppError::from)?;
+            
+            version.ok_or_else(|| AppError::NotFoundError(format!("User with id {} not found", id)))
+        }).instrument(span).await
+    }
+    
+    // Wyszukiwanie fragmentu nazwy użytkownika lub imienia, bez rozróżniania wielkości liter
+    pub async fn search(&self, query: &str) -> Result<Vec<User>, AppError> {
+        let params = format!("query={}", query);
+        let span = create_db_span("search_users", SEARCH_USERS_SQL, &params);
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            let users = sqlx::query_as::<_, User>(SEARCH_USERS_SQL)
+                .bind(contains_pattern(query))
+                .fetch_all(&mut *connection)
+                .await
+                .map_err(AppError::from)?;
+            
+            tracing::debug!("Found {} users matching '{}'", users.len(), query);
+            Ok(users)
+        })).instrument(span).await
+    }
+    
+    // Wyszukiwanie łączone z rankingiem; email przeszukiwany jest tylko na życzenie (dla administratorów)
+    pub async fn search_ranked(&self, query: &str, include_email: bool) -> Result<Vec<RankedUser>, AppError> {
+        let params = format!("query={}, include_email={}", query, include_email);
+        let span = create_db_span("search_users_ranked", RANKED_SEARCH_SQL, &params);
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            let users = sqlx::query_as::<_, RankedUser>(RANKED_SEARCH_SQL)
+                .bind(query)
+                .bind(prefix_pattern(query))
+                .bind(contains_pattern(query))
+                .bind(include_email)
+                .fetch_all(&mut *connection)
+                .await
+                .map_err(AppError::from)?;
+            
+            tracing::debug!("Found {} ranked users matching '{}'", users.len(), query);
+            Ok(users)
+        })).instrument(span).await
+    }
+    
+    pub async fn find_duplicate_pairs(&self, email_similarity: f32, name_similarity: f32) -> Result<Vec<DuplicatePair>, AppError> {
+        let params = format!("email_similarity={}, name_similarity={}", email_similarity, name_similarity);
+        let span = create_db_span("find_duplicate_pairs", DUPLICATE_PAIRS_SQL, &params);
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            let mut tx = connection.begin().await?;
+            // Indeks wybiera kandydatów od niższego z progów, dokładne progi sprawdza WHERE
+            sqlx::query(DUPLICATE_THRESHOLD_SQL)
+                .bind(email_similarity.min(name_similarity).to_string())
+                .execute(&mut *tx)
+                .await?;
+            let pairs = sqlx::query_as::<_, DuplicatePair>(DUPLICATE_PAIRS_SQL)
+                .bind(email_similarity)
+                .bind(name_similarity)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(AppError::from)?;
+            tx.commit().await?;
+            
+            tracing::debug!("Found {} candidate duplicate pairs", pairs.len());
+            Ok(pairs)
+        })).instrument(span).await
+    }
+    
+    pub async fn find_by_ids(&self, ids: &[Uuid]) -> Result<Vec<User>, AppError> {
+        let params = format!("count={}", ids.len());
+        let span = create_db_span(
+            "find_users_by_ids",
+            "SELECT * FROM users WHERE id = ANY($1) AND deleted_at IS NULL",
+            &params,
+        );
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ANY($1) AND deleted_at IS NULL")
+                .bind(ids)
+                .fetch_all(&mut *connection)
+                .await
+                .map_err(AppError::from)
+        })).instrument(span).await
+    }
+    
+    // Scala konto `merge_id` z `keep_id`: usuwa (soft delete) scalane konto i zapisuje
+    // w audit_log wpis pozwalający cofnąć operację, wszystko w jednej transakcji
+    pub async fn merge(&self, keep_id: Uuid, merge_id: Uuid) -> Result<User, AppError> {
+        let params = format!("keep_id={}, merge_id={}", keep_id, merge_id);
+        let span = create_db_span(
+            "merge_users",
+            "UPDATE users SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+            &params,
+        );
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            let mut tx = self.pool.begin().await?;
+            
+            // Blokujemy oba wiersze, żeby równoległe scalenie nie usunęło zachowywanego konta
+            let users = sqlx::query_as::<_, User>(
+                "SELECT * FROM users WHERE id = ANY($1) AND deleted_at IS NULL ORDER BY id FOR UPDATE"
+            )
+            .bind([keep_id, merge_id])
+            .fetch_all(&mut *tx)
+            .await?;
+            
+            let (kept, merged): (Vec<User>, Vec<User>) = users.into_iter().partition(|user| user.id == keep_id);
+            let not_found = |id: Uuid| AppError::NotFoundError(format!("User with id {} not found", id));
+            let kept = kept.into_iter().next().ok_or_else(|| not_found(keep_id))?;
+            let merged = merged.into_iter().next().ok_or_else(|| not_found(merge_id))?;
+            
+            if kept.role == "trainer" && merged.role == "trainer" {
+                return Err(AppError::Conflict("Two trainer accounts cannot be merged".to_string()));
+            }
+            
+            sqlx::query("UPDATE users SET deleted_at = NOW(), updated_at = NOW() WHERE id = $1")
+                .bind(merge_id)
+                .execute(&mut *tx)
+                .await?;
+            
+            // Cofnięcie scalenia: wyczyszczenie deleted_at konta merged_id
+            sqlx::query("INSERT INTO audit_log (action, actor, details) VALUES ($1, $2, $3)")
+                .bind("users_merged")
+                .bind("admin")
+                .bind(serde_json::json!({
+                    "kept_id": keep_id,
+                    "merged_id": merge_id,
+                    "merged_username": merged.username,
+                    "merged_email": merged.email,
+                }))
+                .execute(&mut *tx)
+                .await?;
+            
+            tx.commit().await?;
+            
+            tracing::info!("Merged user {} into {}", merge_id, keep_id);
+            Ok(kept)
+        }).instrument(span).await
+    }
+    
+    // Trenerzy, którzy zgodzili się na publiczny profil; zawieszone konta są pomijane
+    pub async fn find_public_trainers(
+        &self,
+        limit: i64,
+        offset: i64,
+        specialty: Option<&str>,
+    ) -> Result<(Vec<PublicTrainer>, i64), AppError> {
+        let params = format!("limit={}, offset={}, specialty={:?}", limit, offset, specialty);
+        let span = create_db_span("find_public_trainers", PUBLIC_TRAINERS_SQL, &params);
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            let trainers = sqlx::query_as::<_, PublicTrainer>(PUBLIC_TRAINERS_SQL)
+                .bind(limit)
+                .bind(offset)
+                .bind(specialty)
+                .fetch_all(&mut *connection)
+                .await?;
+            
+            let total = sqlx::query_scalar::<_, i64>(COUNT_PUBLIC_TRAINERS_SQL)
+                .bind(specialty)
+                .fetch_one(&mut *connection)
+                .await?;
+            
+            Ok((trainers, total))
+        })).instrument(span).await
+    }
+    
+    pub async fn find_public_trainer(&self, username: &str) -> Result<PublicTrainer, AppError> {
+        let params = format!("username={}", username);
+        let span = create_db_span("find_public_trainer", PUBLIC_TRAINER_SQL, &params);
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            sqlx::query_as::<_, PublicTrainer>(PUBLIC_TRAINER_SQL)
+                .bind(username)
+                .fetch_optional(&mut *connection)
+                .await?
+                .ok_or_else(|| AppError::NotFoundError(format!("Trainer {} not found", username)))
+        })).instrument(span).await
+    }
+    
+    // Konta z rolą czasową, od najbliższego terminu wygaśnięcia
+    pub async fn find_role_assignments(&self, expiring_before: Option<DateTime<Utc>>) -> Result<Vec<User>, AppError> {
+        let params = format!("expiring_before={:?}", expiring_before);
+        let span = create_db_span("find_role_assignments", ROLE_ASSIGNMENTS_SQL, &params);
+        
+        DbMetrics::track("SELECT", "users", || self.read(|mut connection| async move {
+            sqlx::query_as::<_, User>(ROLE_ASSIGNMENTS_SQL)
+                .bind(expiring_before)
+                .fetch_all(&mut *connection)
+                .await
+                .map_err(AppError::from)
+        })).instrument(span).await
+    }
+    
+    // Przywraca rolę domyślną kontom, których rola czasowa wygasła, i czyści pola profilu,
+    // których rola domyślna nie dopuszcza; każda zmiana trafia do audit_log w tym samym
+    // zapytaniu. Zwraca liczbę zmienionych kont
+    pub async fn expire_roles(&self, default_role: &str) -> Result<u64, AppError> {
+        let params = format!("default_role={}", default_role);
+        let span = create_db_span("expire_roles", EXPIRE_ROLES_SQL, &params);
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            let expired = sqlx::query_scalar::<_, i64>(EXPIRE_ROLES_SQL)
+                .bind(default_role)
+                .bind(field_allowed_for_role("bio", default_role))
+                .bind(field_allowed_for_role("hourly_rate", default_role))
+                .bind(field_allowed_for_role("fitness_goals", default_role))
+                .bind(field_allowed_for_role("specialties", default_role))
+                .fetch_one(&self.pool)
+                .await
+                .map_err(AppError::from)?;
+            
+            Ok(expired as u64)
+        }).instrument(span).await
+    }
+    
+    // Reset hasła przez administratora: hasło tymczasowe, wymóg zmiany przy logowaniu,
+    // unieważnienie tokenów i wpis w audit_log, wszystko w jednej transakcji
+    pub async fn force_password_reset(&self, id: Uuid, password_hash: &str) -> Result<User, AppError> {
+        let params = format!("id={}", id);
+        let span = create_db_span(
+            "force_password_reset",
+            "UPDATE users SET password_hash = $2, password_change_required = true, token_version = token_version + 1 WHERE id = $1 AND deleted_at IS NULL",
+            &params,
+        );
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            let mut tx = self.pool.begin().await?;
+            
+            let user = sqlx::query_as::<_, User>(
+                r#"
+                UPDATE users
+                SET password_hash = $2, password_change_required = true,
+                    token_version = token_version + 1, updated_at = NOW()
+                WHERE id = $1 AND deleted_at IS NULL
+                RETURNING *
+                "#
+            )
+            .bind(id)
+            .bind(password_hash)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| AppError::NotFoundError(format!("User with id {} not found", id)))?;
+            
+            sqlx::query("INSERT INTO audit_log (action, actor, details) VALUES ($1, $2, $3)")
+                .bind("password_reset")
+                .bind("admin")
+                .bind(serde_json::json!({ "user_id": id }))
+                .execute(&mut *tx)
+                .await?;
+            
+            tx.commit().await?;
+            
+            tracing::info!("Password of user {} reset by an admin", id);
+            Ok(user)
+        }).instrument(span).await
+    }
+    
+    // Zmiana hasła przez samego użytkownika; zdejmuje wymóg zmiany hasła po resecie
+    pub async fn change_password(&self, id: Uuid, password_hash: &str) -> Result<User, AppError> {
+        let params = format!("id={}", id);
+        let span = create_db_span("change_password", CHANGE_PASSWORD_SQL, &params);
+        
+        DbMetrics::track("UPDATE", "users", || async {
+            sqlx::query_as::<_, User>(CHANGE_PASSWORD_SQL)
+                .bind(id)
+                .bind(password_hash)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or_else(|| AppError::NotFoundError(format!("User with id {} not found", id)))
+        }).instrument(span).await
+    }
+    
+    // Polityka prywatności: usunięte konta nie mogą leżeć w bazie bez końca.
+    // Bezpieczne do wielokrotnego uruchamiania; zwraca liczbę usuniętych kont
+    pub async fn purge_deleted(&self, retention_days: i32) -> Result<u64, AppError> {
+        let params = format!("retention_days={}", retention_days);
+        let span = create_db_span("purge_deleted_users", PURGE_DELETED_USERS_SQL, &params);
+        
+        DbMetrics::track("DELETE", "users", || async {
+            let purged = sqlx::query_scalar::<_, i64>(PURGE_DELETED_USERS_SQL)
+                .bind(retention_days)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(AppError::from)?;
+            
+            Ok(purged as u64)
         }).instrument(span).await
     }
     
@@ -260,17 +1132,17 @@ impl UserRepository {
     pub async fn count_users_by_role(&self) -> Result<Vec<(String, i64)>, AppError> {
         let span = create_db_span(
             "count_users_by_role",
-            "SELECT role, COUNT(*) FROM users GROUP BY role",
+            "SELECT role, COUNT(*) FROM users WHERE deleted_at IS NULL GROUP BY role",
             "None",
         );
         
         DbMetrics::track("SELECT", "users", || async {
             let counts = sqlx::query!(
-                "SELECT role, COUNT(*) as count FROM users GROUP BY role"
+                "SELECT role, COUNT(*) as count FROM users WHERE deleted_at IS NULL GROUP BY role"
             )
             .fetch_all(&self.pool)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
             
             let result = counts
                 .into_iter()
@@ -285,19 +1157,346 @@ impl UserRepository {
     pub async fn count_inactive_users(&self) -> Result<i64, AppError> {
         let span = create_db_span(
             "count_inactive_users",
-            "SELECT COUNT(*) FROM users WHERE active = false",
+            "SELECT COUNT(*) FROM users WHERE active = false AND deleted_at IS NULL",
             "None",
         );
         
         DbMetrics::track("SELECT", "users", || async {
             let result = sqlx::query!(
-                "SELECT COUNT(*) as count FROM users WHERE active = false"
+                "SELECT COUNT(*) as count FROM users WHERE active = false AND deleted_at IS NULL"
             )
             .fetch_one(&self.pool)
             .await
-            .map_err(AppError::DatabaseError)?;
+            .map_err(AppError::from)?;
             
             Ok(result.count.unwrap_or(0))
         }).instrument(span).await
     }
-}
\ No newline at end of file
+    
+    // Sprawdza, kto używa danej nazwy użytkownika / adresu email (z pominięciem exclude_id)
+    pub async fn identifier_status(
+        &self,
+        identifier: Identifier<'_>,
+        exclude_id: Option<Uuid>,
+    ) -> Result<IdentifierStatus, AppError> {
+        let (sql, value) = match identifier {
+            Identifier::Username(username) => (
+                "SELECT COUNT(*) FILTER (WHERE deleted_at IS NULL), COUNT(*) FILTER (WHERE deleted_at IS NOT NULL) \
+                 FROM users WHERE username = $1 AND ($2::uuid IS NULL OR id != $2)",
+                username.as_str(),
+            ),
+            Identifier::Email(email) => (
+                "SELECT COUNT(*) FILTER (WHERE deleted_at IS NULL), COUNT(*) FILTER (WHERE deleted_at IS NOT NULL) \
+                 FROM users WHERE lower(email) = $1 AND ($2::uuid IS NULL OR id != $2)",
+                email.as_str(),
+            ),
+        };
+        let span = create_db_span("identifier_status", sql, "None");
+        
+        DbMetrics::track("SELECT", "users", || async {
+            let (active, deleted) = sqlx::query_as::<_, (i64, i64)>(sql)
+                .bind(value)
+                .bind(exclude_id)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(AppError::from)?;
+            
+            Ok(if active > 0 {
+                IdentifierStatus::InUse
+            } else if deleted > 0 {
+                IdentifierStatus::HeldByDeletedAccount
+            } else {
+                IdentifierStatus::Available
+            })
+        }).instrument(span).await
+    }
+}
+
+// Zapis zdarzeń administracyjnych i bezpieczeństwa
+pub struct AuditLogRepository {
+    pool: PgPool,
+}
+
+impl AuditLogRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, action: &str, actor: &str, details: serde_json::Value) -> Result<(), AppError> {
+        let params = format!("action={}, actor={}", action, actor);
+        let span = create_db_span(
+            "record_audit_event",
+            "INSERT INTO audit_log (action, actor, details) VALUES ($1, $2, $3)",
+            &params,
+        );
+
+        DbMetrics::track("INSERT", "audit_log", || async {
+            sqlx::query("INSERT INTO audit_log (action, actor, details) VALUES ($1, $2, $3)")
+                .bind(action)
+                .bind(actor)
+                .bind(details)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        }).instrument(span).await
+    }
+}
+
+// Przechwycone nieudane żądania, przechowywane przez ograniczony czas
+pub struct FailedRequestRepository {
+    pool: PgPool,
+}
+
+impl FailedRequestRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, request: &FailedRequest) -> Result<(), AppError> {
+        let params = format!("request_id={}, route={}", request.request_id, request.route);
+        let span = create_db_span(
+            "record_failed_request",
+            "INSERT INTO failed_requests (request_id, method, route, status, error_code, body, body_size, body_truncated) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &params,
+        );
+
+        DbMetrics::track("INSERT", "failed_requests", || async {
+            sqlx::query(
+                r#"
+                INSERT INTO failed_requests (request_id, method, route, status, error_code, body, body_size, body_truncated)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                "#
+            )
+            .bind(request.request_id)
+            .bind(&request.method)
+            .bind(&request.route)
+            .bind(request.status)
+            .bind(&request.error_code)
+            .bind(&request.body)
+            .bind(request.body_size)
+            .bind(request.body_truncated)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }).instrument(span).await
+    }
+
+    pub async fn find_by_request_id(&self, request_id: Uuid) -> Result<FailedRequest, AppError> {
+        let params = format!("request_id={}", request_id);
+        let span = create_db_span(
+            "find_failed_request",
+            "SELECT * FROM failed_requests WHERE request_id = $1",
+            &params,
+        );
+
+        DbMetrics::track("SELECT", "failed_requests", || async {
+            sqlx::query_as::<_, FailedRequest>("SELECT * FROM failed_requests WHERE request_id = $1")
+                .bind(request_id)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or_else(|| AppError::NotFoundError(format!("No captured request with id {}", request_id)))
+        }).instrument(span).await
+    }
+
+    // Usuwa wpisy starsze niż okres retencji; zwraca liczbę usuniętych
+    pub async fn purge_older_than(&self, retention_days: i32) -> Result<u64, AppError> {
+        let params = format!("retention_days={}", retention_days);
+        let span = create_db_span(
+            "purge_failed_requests",
+            "DELETE FROM failed_requests WHERE created_at < NOW() - make_interval(days => $1)",
+            &params,
+        );
+
+        DbMetrics::track("DELETE", "failed_requests", || async {
+            let result = sqlx::query("DELETE FROM failed_requests WHERE created_at < NOW() - make_interval(days => $1)")
+                .bind(retention_days)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        }).instrument(span).await
+    }
+}
+
+// Słownik specjalizacji trenerów i ich przypisania
+pub struct SpecialtyRepository {
+    pool: PgPool,
+}
+
+impl SpecialtyRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn find_all(&self) -> Result<Vec<Specialty>, AppError> {
+        let span = create_db_span("find_specialties", "SELECT slug, name FROM specialties ORDER BY slug", "None");
+        
+        DbMetrics::track("SELECT", "specialties", || async {
+            sqlx::query_as::<_, Specialty>("SELECT slug, name FROM specialties ORDER BY slug")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(AppError::from)
+        }).instrument(span).await
+    }
+
+    // Wartości spoza słownika, w kolejności podania
+    pub async fn find_unknown(&self, slugs: &[String]) -> Result<Vec<String>, AppError> {
+        let params = format!("slugs={:?}", slugs);
+        let span = create_db_span("find_unknown_specialties", UNKNOWN_SPECIALTIES_SQL, &params);
+        
+        DbMetrics::track("SELECT", "specialties", || async {
+            sqlx::query_scalar::<_, String>(UNKNOWN_SPECIALTIES_SQL)
+                .bind(slugs)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(AppError::from)
+        }).instrument(span).await
+    }
+
+    pub async fn find_for_user(&self, user_id: Uuid) -> Result<Vec<String>, AppError> {
+        let params = format!("user_id={}", user_id);
+        let span = create_db_span(
+            "find_trainer_specialties",
+            "SELECT specialty FROM trainer_specialties WHERE user_id = $1 ORDER BY specialty",
+            &params,
+        );
+        
+        DbMetrics::track("SELECT", "trainer_specialties", || async {
+            sqlx::query_scalar::<_, String>(
+                "SELECT specialty FROM trainer_specialties WHERE user_id = $1 ORDER BY specialty"
+            )
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::from)
+        }).instrument(span).await
+    }
+}
+
+pub struct PhoneVerificationRepository {
+    pool: PgPool,
+}
+
+impl PhoneVerificationRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // Zapisuje nowy kod, zastępując poprzedni. Licznik prób zerujemy dopiero po wygaśnięciu
+    // poprzedniego kodu, a kod wysłany w ciągu `resend_interval_secs` blokuje kolejny;
+    // false, gdy nowy kod nie został zapisany z powodu tego odstępu
+    pub async fn replace(
+        &self,
+        user_id: Uuid,
+        phone_number: &str,
+        code_hash: &str,
+        ttl_secs: u64,
+        resend_interval_secs: u64,
+    ) -> Result<bool, AppError> {
+        let params = format!("user_id={}, ttl_secs={}, resend_interval_secs={}", user_id, ttl_secs, resend_interval_secs);
+        let span = create_db_span("replace_phone_verification", REPLACE_PHONE_VERIFICATION_SQL, &params);
+
+        DbMetrics::track("INSERT", "phone_verifications", || async {
+            let result = sqlx::query(REPLACE_PHONE_VERIFICATION_SQL)
+                .bind(user_id)
+                .bind(phone_number)
+                .bind(code_hash)
+                .bind(ttl_secs as f64)
+                .bind(resend_interval_secs as f64)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        }).instrument(span).await
+    }
+
+    pub async fn find(&self, user_id: Uuid) -> Result<Option<PhoneVerification>, AppError> {
+        let params = format!("user_id={}", user_id);
+        let span = create_db_span(
+            "find_phone_verification",
+            "SELECT * FROM phone_verifications WHERE user_id = $1",
+            &params,
+        );
+
+        DbMetrics::track("SELECT", "phone_verifications", || async {
+            sqlx::query_as::<_, PhoneVerification>("SELECT * FROM phone_verifications WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(AppError::from)
+        }).instrument(span).await
+    }
+
+    // Zużywa jedną próbę przed porównaniem kodu, więc równoległe żądania nie
+    // przekroczą limitu; None, gdy kodu nie ma lub próby się wyczerpały
+    pub async fn consume_attempt(&self, user_id: Uuid, max_attempts: u32) -> Result<Option<PhoneVerification>, AppError> {
+        let params = format!("user_id={}, max_attempts={}", user_id, max_attempts);
+        let span = create_db_span(
+            "consume_phone_verification_attempt",
+            "UPDATE phone_verifications SET attempts = attempts + 1 WHERE user_id = $1 AND attempts < $2 RETURNING *",
+            &params,
+        );
+
+        DbMetrics::track("UPDATE", "phone_verifications", || async {
+            sqlx::query_as::<_, PhoneVerification>(
+                "UPDATE phone_verifications SET attempts = attempts + 1 WHERE user_id = $1 AND attempts < $2 RETURNING *"
+            )
+            .bind(user_id)
+            .bind(max_attempts as i32)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(AppError::from)
+        }).instrument(span).await
+    }
+
+    pub async fn delete(&self, user_id: Uuid) -> Result<(), AppError> {
+        let params = format!("user_id={}", user_id);
+        let span = create_db_span(
+            "delete_phone_verification",
+            "DELETE FROM phone_verifications WHERE user_id = $1",
+            &params,
+        );
+
+        DbMetrics::track("DELETE", "phone_verifications", || async {
+            sqlx::query("DELETE FROM phone_verifications WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        }).instrument(span).await
+    }
+
+    // Oznacza telefon jako potwierdzony, o ile użytkownik nadal ma ten sam numer,
+    // i usuwa wykorzystany kod; None, gdy numer zmienił się po wysłaniu kodu
+    pub async fn confirm(&self, user_id: Uuid, phone_number: &str) -> Result<Option<User>, AppError> {
+        let params = format!("user_id={}", user_id);
+        let span = create_db_span(
+            "confirm_phone_verification",
+            "UPDATE users SET phone_verified_at = NOW() WHERE id = $1 AND phone_number = $2 AND deleted_at IS NULL RETURNING *",
+            &params,
+        );
+
+        DbMetrics::track("UPDATE", "users", || async {
+            let mut tx = self.pool.begin().await?;
+
+            let user = sqlx::query_as::<_, User>(
+                r#"
+                UPDATE users SET phone_verified_at = NOW(), updated_at = NOW()
+                WHERE id = $1 AND phone_number = $2 AND deleted_at IS NULL
+                RETURNING *
+                "#
+            )
+            .bind(user_id)
+            .bind(phone_number)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            sqlx::query("DELETE FROM phone_verifications WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+            Ok(user)
+        }).instrument(span).await
+    }
+}
+
@@ -0,0 +1,414 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+use jsonschema::{
+    error::{TypeKind, ValidationErrorKind},
+    Validator,
+};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Value};
+use std::{cell::RefCell, fmt, ops::Deref};
+
+use crate::config::Config;
+use crate::error::{AppError, FieldError};
+use crate::monitoring::{RequestTimings, Timer};
+use crate::normalize::{trim_field_value, trim_strings};
+use crate::types::unknown_variant_error;
+use crate::models::{BulkCreateUsersRequest, ChangePasswordRequest, CreateUserRequest, LoginRequest, MaintenanceRequest, PasswordCheckRequest, RegistrationDraft, UpdateUserRequest, ValidateFieldRequest};
+
+// Schematy ładowane są w czasie kompilacji, po jednym na endpoint
+lazy_static::lazy_static! {
+    static ref CREATE_USER_SCHEMA: Validator = compile_schema(include_str!("../schemas/create_user.json"));
+    static ref UPDATE_USER_SCHEMA: Validator = compile_schema(include_str!("../schemas/update_user.json"));
+    static ref LOGIN_SCHEMA: Validator = compile_schema(include_str!("../schemas/login.json"));
+    static ref BULK_CREATE_USERS_SCHEMA: Validator = compile_schema(include_str!("../schemas/bulk_create_users.json"));
+    static ref REGISTRATION_DRAFT_SCHEMA: Validator = compile_draft_schema(include_str!("../schemas/create_user.json"));
+    static ref MAINTENANCE_SCHEMA: Validator = compile_schema(include_str!("../schemas/maintenance.json"));
+    static ref CHANGE_PASSWORD_SCHEMA: Validator = compile_schema(include_str!("../schemas/change_password.json"));
+    static ref CHECK_PASSWORD_SCHEMA: Validator = compile_schema(include_str!("../schemas/check_password.json"));
+    static ref VALIDATE_FIELD_SCHEMA: Validator = compile_schema(include_str!("../schemas/validate_field.json"));
+}
+
+fn compile_schema(source: &str) -> Validator {
+    build_validator(&parse_schema(source))
+}
+
+// Szkic rejestracji to formularz tworzenia użytkownika, w którym żadne pole nie jest
+// jeszcze wymagane; wspólne źródło sprawia, że reguły pól nie mogą się rozjechać
+fn compile_draft_schema(source: &str) -> Validator {
+    let mut schema = parse_schema(source);
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("required");
+        object.insert("title".to_string(), Value::from("RegistrationDraft"));
+    }
+    build_validator(&schema)
+}
+
+fn parse_schema(source: &str) -> Value {
+    serde_json::from_str(source).expect("Request schema is not valid JSON")
+}
+
+fn build_validator(schema: &Value) -> Validator {
+    jsonschema::options()
+        .should_validate_formats(true)
+        .build(schema)
+        .expect("Request schema failed to compile")
+}
+
+/// Request body types that have a JSON Schema checked before deserialization
+pub trait RequestSchema {
+    fn schema() -> &'static Validator;
+
+    /// Normalizes the body before the schema check; by default trims every string value
+    fn normalize(body: &mut Value) {
+        trim_strings(body);
+    }
+}
+
+impl RequestSchema for CreateUserRequest {
+    fn schema() -> &'static Validator {
+        &CREATE_USER_SCHEMA
+    }
+}
+
+impl RequestSchema for UpdateUserRequest {
+    fn schema() -> &'static Validator {
+        &UPDATE_USER_SCHEMA
+    }
+}
+
+impl RequestSchema for LoginRequest {
+    fn schema() -> &'static Validator {
+        &LOGIN_SCHEMA
+    }
+}
+
+impl RequestSchema for BulkCreateUsersRequest {
+    fn schema() -> &'static Validator {
+        &BULK_CREATE_USERS_SCHEMA
+    }
+}
+
+// Te same definicje pól co przy tworzeniu użytkownika, tylko bez wymaganych pól
+impl RequestSchema for RegistrationDraft {
+    fn schema() -> &'static Validator {
+        &REGISTRATION_DRAFT_SCHEMA
+    }
+}
+
+impl RequestSchema for MaintenanceRequest {
+    fn schema() -> &'static Validator {
+        &MAINTENANCE_SCHEMA
+    }
+}
+
+impl RequestSchema for ChangePasswordRequest {
+    fn schema() -> &'static Validator {
+        &CHANGE_PASSWORD_SCHEMA
+    }
+}
+
+impl RequestSchema for PasswordCheckRequest {
+    fn schema() -> &'static Validator {
+        &CHECK_PASSWORD_SCHEMA
+    }
+}
+
+impl RequestSchema for ValidateFieldRequest {
+    fn schema() -> &'static Validator {
+        &VALIDATE_FIELD_SCHEMA
+    }
+
+    // Wartość przycinamy tak, jak przycinane jest pole, którego dotyczy
+    fn normalize(body: &mut Value) {
+        let value = body.as_object_mut().and_then(|map| map.remove("value"));
+        trim_strings(body);
+        if let Some(value) = value {
+            let field = body.get("field").and_then(Value::as_str);
+            body["value"] = match (field, value) {
+                (Some(field), Value::String(text)) => Value::String(trim_field_value(field, &text).to_string()),
+                (_, value) => value,
+            };
+        }
+    }
+}
+
+/// Validates a JSON document against a schema, collecting every violation.
+///
+/// A value of the wrong JSON type means the body does not have the shape of the
+/// request, so type mismatches fail with `TypeMismatch` (each with the `expected`
+/// type) before any value rule is reported as a validation error.
+pub fn validate_against(schema: &Validator, body: &Value) -> Result<(), AppError> {
+    let mut type_mismatches = Vec::new();
+    let errors: Vec<FieldError> = schema
+        .iter_errors(body)
+        .filter_map(|error| {
+            let instance_path = error.instance_path().as_str().to_string();
+            match error.kind() {
+                // Dla brakujących pól wskazujemy samo pole, a nie obiekt nadrzędny
+                ValidationErrorKind::Required { property } => Some(FieldError::new(
+                    push_pointer(&instance_path, property.as_str().unwrap_or_default()),
+                    error.to_string(),
+                )),
+                ValidationErrorKind::Type { kind } => {
+                    type_mismatches
+                        .push(FieldError::new(instance_path, error.to_string()).with_expected(expected_types(kind)));
+                    None
+                }
+                _ => Some(FieldError::new(instance_path, error.to_string())),
+            }
+        })
+        .collect();
+
+    if !type_mismatches.is_empty() {
+        return Err(AppError::TypeMismatch(type_mismatches));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::FieldValidationError(errors))
+    }
+}
+
+fn expected_types(kind: &TypeKind) -> String {
+    match kind {
+        TypeKind::Single(json_type) => json_type.to_string(),
+        TypeKind::Multiple(types) => types
+            .iter()
+            .map(|json_type| json_type.to_string())
+            .collect::<Vec<_>>()
+            .join(" or "),
+    }
+}
+
+// Dokleja segment do JSON Pointera z escapowaniem według RFC 6901
+fn push_pointer(base: &str, segment: &str) -> String {
+    format!("{}/{}", base, segment.replace('~', "~0").replace('/', "~1"))
+}
+
+/// Parses a request body, rejecting trailing data and, when `reject_duplicates`
+/// is set, objects that repeat a key
+pub fn parse_body(bytes: &[u8], reject_duplicates: bool) -> Result<Value, AppError> {
+    let duplicate = RefCell::new(None);
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+
+    let seed = ValueSeed {
+        pointer: String::new(),
+        reject_duplicates,
+        duplicate: &duplicate,
+    };
+    let parsed = seed
+        .deserialize(&mut deserializer)
+        .and_then(|value| deserializer.end().map(|_| value));
+
+    parsed.map_err(|e| match duplicate.into_inner() {
+        Some(pointer) => AppError::MalformedBody(FieldError::new(
+            pointer,
+            "Duplicate key in JSON object",
+        )),
+        None => AppError::MalformedBody(FieldError::new("", format!("Invalid JSON: {}", e))),
+    })
+}
+
+// Buduje serde_json::Value, śledząc bieżącą ścieżkę na potrzeby wykrywania duplikatów
+struct ValueSeed<'a> {
+    pointer: String,
+    reject_duplicates: bool,
+    duplicate: &'a RefCell<Option<String>>,
+}
+
+impl<'a> ValueSeed<'a> {
+    fn child(&self, segment: &str) -> ValueSeed<'a> {
+        ValueSeed {
+            pointer: push_pointer(&self.pointer, segment),
+            reject_duplicates: self.reject_duplicates,
+            duplicate: self.duplicate,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any valid JSON value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(self.child(&values.len().to_string()))? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut object = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if self.reject_duplicates && object.contains_key(&key) {
+                *self.duplicate.borrow_mut() = Some(push_pointer(&self.pointer, &key));
+                return Err(de::Error::custom(format!("duplicate key `{}`", key)));
+            }
+            let value = map.next_value_seed(self.child(&key))?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+/// Deserializes a validated document into `T`, returning the pointers of any
+/// keys `T` does not know about
+pub fn deserialize_body<T: DeserializeOwned>(body: Value) -> Result<(T, Vec<String>), AppError> {
+    let mut unknown = Vec::new();
+    let mut track_unknown = |path: serde_ignored::Path| unknown.push(ignored_pointer(&path));
+    let deserializer = serde_ignored::Deserializer::new(body, &mut track_unknown);
+
+    let data = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let pointer = e.path().iter().fold(String::new(), |pointer, segment| match segment {
+            serde_path_to_error::Segment::Seq { index } => push_pointer(&pointer, &index.to_string()),
+            serde_path_to_error::Segment::Map { key } => push_pointer(&pointer, key),
+            serde_path_to_error::Segment::Enum { variant } => push_pointer(&pointer, variant),
+            serde_path_to_error::Segment::Unknown => pointer,
+        });
+        let message = e.inner().to_string();
+        // Wartość spoza wariantów enuma to błąd walidacji, a nie niepoprawna treść
+        let field = pointer.rsplit('/').next().unwrap_or_default();
+        if let Some(error) = unknown_variant_error(field, &message) {
+            return error;
+        }
+        // serde opisuje błędy typu jako "invalid type: ..., expected X"
+        let expected = message
+            .split_once(", expected ")
+            .map(|(_, expected)| expected.to_string());
+
+        let mut error = FieldError::new(pointer, message);
+        error.expected = expected;
+        AppError::MalformedBody(error)
+    })?;
+
+    Ok((data, unknown))
+}
+
+fn ignored_pointer(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => push_pointer(&ignored_pointer(parent), &index.to_string()),
+        serde_ignored::Path::Map { parent, key } => push_pointer(&ignored_pointer(parent), key),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_pointer(parent),
+    }
+}
+
+// Pełny potok: parsowanie, przycięcie wartości, schemat (najpierw typy, potem reguły),
+// deserializacja i kontrola nieznanych pól
+fn validate_body<T: DeserializeOwned + RequestSchema>(bytes: &[u8], strict: bool) -> Result<T, AppError> {
+    let mut body = parse_body(bytes, strict)?;
+    T::normalize(&mut body);
+    validate_against(T::schema(), &body)?;
+
+    let (data, unknown) = deserialize_body(body)?;
+    if strict && !unknown.is_empty() {
+        return Err(AppError::UnknownFields(unknown));
+    }
+    Ok(data)
+}
+
+/// JSON extractor that runs the type's schema before handing the body to the handler.
+///
+/// With `strict_json` enabled in the config, duplicate keys and keys unknown to `T`
+/// are rejected instead of being silently dropped.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: DeserializeOwned + RequestSchema + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let strict = req
+            .app_data::<web::Data<Config>>()
+            .map(|config| config.strict_json)
+            .unwrap_or(false);
+        let is_json = req.content_type().ends_with("json");
+        let bytes = web::Bytes::from_request(req, payload);
+
+        Box::pin(async move {
+            if !is_json {
+                return Err(AppError::MalformedBody(FieldError::new(
+                    "",
+                    "Content-Type must be application/json",
+                ))
+                .into());
+            }
+
+            let bytes = bytes.await?;
+
+            let timer = Timer::new();
+            let result = validate_body::<T>(&bytes, strict);
+            RequestTimings::record_validation(timer.elapsed_seconds());
+
+            Ok(ValidatedJson(result?))
+        })
+    }
+}
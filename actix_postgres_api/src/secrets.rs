@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Where secrets (JWT key, admin key) are loaded from at startup
+pub trait SecretSource: fmt::Display + Send + Sync {
+    /// Returns the secret called `name` (e.g. `JWT_SECRET`), or `None` if it is not set or empty
+    fn secret(&self, name: &str) -> Result<Option<String>>;
+
+    /// Whether a missing JWT key may be replaced by a random one, as in local development
+    fn allows_development_fallback(&self) -> bool {
+        false
+    }
+}
+
+/// Reads secrets from environment variables of the same name (default)
+pub struct EnvSecretSource;
+
+impl SecretSource for EnvSecretSource {
+    fn secret(&self, name: &str) -> Result<Option<String>> {
+        Ok(env::var(name).ok().filter(|secret| !secret.is_empty()))
+    }
+
+    // Losowy klucz JWT tylko na wyraźne życzenie, np. w środowisku deweloperskim
+    fn allows_development_fallback(&self) -> bool {
+        env::var("ALLOW_EPHEMERAL_JWT_SECRET").is_ok_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+}
+
+impl fmt::Display for EnvSecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("environment")
+    }
+}
+
+/// Reads each secret from its own file in a mounted directory, e.g. `/run/secrets/jwt_secret`
+pub struct FileSecretSource {
+    dir: PathBuf,
+}
+
+impl FileSecretSource {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl SecretSource for FileSecretSource {
+    // Nazwa pliku to nazwa sekretu małymi literami, zgodnie z konwencją Docker/Kubernetes
+    fn secret(&self, name: &str) -> Result<Option<String>> {
+        let path = self.dir.join(name.to_lowercase());
+        match fs::read_to_string(&path) {
+            // Edytory i `echo` dopisują końcowy znak nowej linii, który nie jest częścią sekretu;
+            // pusty plik traktujemy jak brak sekretu
+            Ok(contents) => {
+                let secret = contents.trim_end_matches(['\n', '\r']);
+                Ok(Some(secret.to_string()).filter(|secret| !secret.is_empty()))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read secret file {}", path.display())),
+        }
+    }
+}
+
+impl fmt::Display for FileSecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "secret files in {}", self.dir.display())
+    }
+}
+
+/// Picks the secret source from `SECRETS_SOURCE` (`env` or `file`) and `SECRETS_DIR`
+pub fn secret_source_from_env() -> Result<Box<dyn SecretSource>> {
+    match env::var("SECRETS_SOURCE").unwrap_or_else(|_| "env".to_string()).to_lowercase().as_str() {
+        "env" => Ok(Box::new(EnvSecretSource)),
+        "file" => Ok(Box::new(FileSecretSource::new(
+            env::var("SECRETS_DIR").unwrap_or_else(|_| "/run/secrets".to_string()),
+        ))),
+        other => Err(anyhow!("Invalid SECRETS_SOURCE '{}', expected 'env' or 'file'", other)),
+    }
+}
+
+/// Loads a secret that must be present and non-empty, naming the source when it is missing
+pub fn require_secret(source: &dyn SecretSource, name: &str) -> Result<String> {
+    match source.secret(name)? {
+        Some(secret) if !secret.is_empty() => Ok(secret),
+        _ => Err(anyhow!("Required secret {} was not found (or is empty) in {}", name, source)),
+    }
+}
@@ -0,0 +1,132 @@
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+use chrono::Utc;
+use futures::future::LocalBoxFuture;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::User;
+use crate::repository::UserRepository;
+
+/// Claims carried by an access token
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub ver: i32,  // token_version użytkownika w chwili wydania tokenu
+    #[serde(default)]
+    pub role: Option<String>,  // Rola w chwili wydania, do limitów żądań; brak w starszych tokenach
+    pub jti: Uuid,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Issues a signed access token for `user`, valid for `ttl_secs`
+pub fn issue_access_token(user: &User, secret: &str, ttl_secs: u64) -> Result<String, AppError> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user.id,
+        ver: user.token_version,
+        role: Some(user.role.clone()),
+        jti: Uuid::new_v4(),
+        iat: now,
+        exp: now + ttl_secs as i64,
+    };
+
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))
+        .map_err(|e| AppError::InternalServerError(format!("Token encoding error: {}", e)))
+}
+
+/// Checks the signature and expiry of an access token, without consulting the database
+pub fn decode_access_token(token: &str, secret: &str) -> Result<Claims, AppError> {
+    decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default())
+        .map(|data| data.claims)
+        .map_err(|_| AppError::Unauthorized("Invalid or expired access token".to_string()))
+}
+
+/// Fully verifies an access token: signature, expiry and the user's current token version
+pub async fn verify_access_token(token: &str, secret: &str, repo: &UserRepository) -> Result<User, AppError> {
+    let claims = decode_access_token(token, secret)?;
+
+    let user = repo.find_by_id(claims.sub).await.map_err(|e| match e {
+        AppError::NotFoundError(_) => AppError::Unauthorized("Account no longer exists".to_string()),
+        other => other,
+    })?;
+
+    if !user.active {
+        return Err(AppError::Unauthorized("Account is inactive".to_string()));
+    }
+
+    // Podbicie token_version (np. przez logout-all) unieważnia wszystkie starsze tokeny
+    if user.token_version != claims.ver {
+        return Err(AppError::Unauthorized("Access token has been revoked".to_string()));
+    }
+
+    Ok(user)
+}
+
+/// The access token from the `Authorization: Bearer` header, if any
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// Wspólna część ekstraktorów: token z nagłówka Authorization i pełna weryfikacja
+fn authenticate_request(req: &HttpRequest) -> LocalBoxFuture<'static, Result<User, AppError>> {
+    let token = bearer_token(req).map(str::to_string);
+    let config = req.app_data::<web::Data<Config>>().cloned();
+    let pool = req.app_data::<web::Data<PgPool>>().cloned();
+
+    Box::pin(async move {
+        let (config, pool) = config.zip(pool).ok_or_else(|| {
+            AppError::InternalServerError("Authentication is not configured".to_string())
+        })?;
+        let token = token.ok_or_else(|| {
+            AppError::Unauthorized("Missing bearer access token".to_string())
+        })?;
+
+        let repo = UserRepository::new(pool.get_ref().clone());
+        verify_access_token(&token, &config.jwt_secret, &repo).await
+    })
+}
+
+/// Extractor for the user behind a valid `Authorization: Bearer` access token.
+/// Rejects accounts that still have to replace a temporary password
+pub struct AuthenticatedUser(pub User);
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = authenticate_request(req);
+
+        Box::pin(async move {
+            let user = user.await?;
+            // Po resecie przez administratora jedyną dozwoloną operacją jest zmiana hasła
+            if user.password_change_required {
+                return Err(AppError::PasswordChangeRequired.into());
+            }
+            Ok(AuthenticatedUser(user))
+        })
+    }
+}
+
+/// Like [`AuthenticatedUser`], but also admits accounts that must change their password;
+/// only for the password change endpoint
+pub struct PasswordChangeUser(pub User);
+
+impl FromRequest for PasswordChangeUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = authenticate_request(req);
+
+        Box::pin(async move { Ok(PasswordChangeUser(user.await?)) })
+    }
+}
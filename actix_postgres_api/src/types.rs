@@ -0,0 +1,162 @@
+use serde::{de, Deserialize, Deserializer};
+use std::fmt;
+
+use crate::auth_utils::{validate_email, validate_phone_number, validate_username};
+use crate::error::AppError;
+
+// Typy wartości, których samo istnienie gwarantuje poprawność - konstruktor
+// normalizuje dane wejściowe i uruchamia odpowiedni walidator
+
+/// Validated email address, normalized to trimmed lowercase
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Email(String);
+
+impl Email {
+    pub fn parse(raw: &str) -> Result<Self, AppError> {
+        let normalized = raw.trim().to_lowercase();
+        validate_email(&normalized)?;
+        Ok(Self(normalized))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// Validated username, with surrounding whitespace removed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Username(String);
+
+impl Username {
+    pub fn parse(raw: &str) -> Result<Self, AppError> {
+        let normalized = raw.trim();
+        validate_username(normalized)?;
+        Ok(Self(normalized.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// Validated phone number, with surrounding whitespace removed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    pub fn parse(raw: &str) -> Result<Self, AppError> {
+        let normalized = raw.trim();
+        validate_phone_number(normalized)?;
+        Ok(Self(normalized.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+macro_rules! impl_str_newtype {
+    ($($name:ident),*) => {
+        $(
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.write_str(&self.0)
+                }
+            }
+
+            impl AsRef<str> for $name {
+                fn as_ref(&self) -> &str {
+                    &self.0
+                }
+            }
+
+            impl TryFrom<String> for $name {
+                type Error = AppError;
+
+                fn try_from(value: String) -> Result<Self, Self::Error> {
+                    Self::parse(&value)
+                }
+            }
+
+            impl From<$name> for String {
+                fn from(value: $name) -> Self {
+                    value.0
+                }
+            }
+        )*
+    };
+}
+
+impl_str_newtype!(Email, Username, PhoneNumber);
+
+/// String-valued enum accepted in requests (e.g. `role`). Lookup is case-insensitive,
+/// and an unknown value becomes a `ValidationError` listing the allowed values.
+pub trait ParseEnum: Sized + Copy + PartialEq + 'static {
+    /// Field name used in error messages
+    const FIELD: &'static str;
+    /// Wire names, in the same order as `VARIANTS`
+    const NAMES: &'static [&'static str];
+    const VARIANTS: &'static [Self];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .position(|candidate| candidate.eq_ignore_ascii_case(name.trim()))
+            .map(|index| Self::VARIANTS[index])
+    }
+
+    fn parse(raw: &str) -> Result<Self, AppError> {
+        Self::from_name(raw).ok_or_else(|| invalid_enum_value(Self::FIELD, raw, Self::NAMES))
+    }
+
+    fn as_str(&self) -> &'static str {
+        let index = Self::VARIANTS
+            .iter()
+            .position(|variant| variant == self)
+            .expect("ParseEnum::VARIANTS must list every variant");
+        Self::NAMES[index]
+    }
+}
+
+/// The error every enum-like field reports for a value outside its variants
+pub fn invalid_enum_value(field: &str, value: &str, allowed: &[&str]) -> AppError {
+    let allowed: Vec<String> = allowed.iter().map(|name| format!("'{}'", name)).collect();
+    AppError::ValidationError(format!(
+        "Invalid {} '{}'. Allowed values: {}",
+        field,
+        value,
+        allowed.join(", ")
+    ))
+}
+
+/// Turns serde's "unknown variant `x`, expected `a` or `b`" (or "expected one of `a`, `b`")
+/// for `field` into `invalid_enum_value`; `None` for any other message
+pub fn unknown_variant_error(field: &str, message: &str) -> Option<AppError> {
+    let rest = message.strip_prefix("unknown variant `")?;
+    let (value, expected) = rest.split_once("`, expected ")?;
+    let allowed: Vec<&str> = expected.split('`').skip(1).step_by(2).collect();
+    Some(invalid_enum_value(field, value, &allowed))
+}
+
+/// Deserializer for `ParseEnum` types; an unknown value is reported as serde's
+/// `unknown variant`, which `unknown_variant_error` turns into `invalid_enum_value`
+pub fn deserialize_enum<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ParseEnum,
+{
+    let raw = String::deserialize(deserializer)?;
+    T::from_name(&raw).ok_or_else(|| de::Error::unknown_variant(&raw, T::NAMES))
+}